@@ -0,0 +1,465 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::sync::Arc;
+
+use arrow::array::{Array, AsArray, BooleanArray, UInt32Array};
+use arrow::compute::{cast, concat_batches, filter_record_batch, take_record_batch};
+use arrow::datatypes::{DataType, SchemaRef};
+use arrow::row::{OwnedRow, RowConverter, SortField};
+use arrow_array::{ArrayRef, RecordBatch};
+use clap::Parser;
+use eyre::{eyre, Context};
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use parquet::arrow::arrow_reader::{ArrowReaderMetadata, ParquetRecordBatchReaderBuilder};
+use parquet::file::metadata::RowGroupMetaData;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::types::ColumnDescriptor;
+
+use crate::cmd::stats::{column_index, min_max, stat_values, StatValue};
+use crate::cmd::utils::*;
+use crate::cmd::writer::{copy_row_group, encode_row_group, WriterArgs};
+
+#[derive(Parser, Debug)]
+/// apply a change file of inserts, updates and deletes to a base parquet file
+///
+/// The whole change file is held in memory, so it should stay well below the
+/// available memory; split larger change sets and apply them one after another.
+/// Row groups of the base are skipped on the statistics of the first key column
+/// only, put the most selective key first.
+pub struct Args {
+    #[arg(short, long)]
+    /// Path to the new base file
+    output: String,
+
+    #[arg(short, long, value_delimiter = ',', required = true)]
+    /// key columns identifying a row
+    key: Vec<String>,
+
+    #[arg(long, default_value = "op")]
+    /// column of the change file holding the operation
+    op_column: String,
+
+    #[arg(long, default_value = "I")]
+    /// op value of inserted rows
+    insert: String,
+
+    #[arg(long, default_value = "U")]
+    /// op value of updated rows
+    update: String,
+
+    #[arg(long, default_value = "D")]
+    /// op value of deleted rows
+    delete: String,
+
+    #[command(flatten)]
+    writer: WriterArgs,
+
+    /// base parquet file
+    base: String,
+
+    /// change parquet file, with the base columns and the op column
+    changes: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// the change set, at most one change per key, fully held in memory
+struct Changes {
+    batch: RecordBatch,
+    ops: HashMap<OwnedRow, (Op, usize)>,
+    /// sorted values of the first key column, None if row groups can not be pruned
+    first_keys: Option<Vec<StatValue>>,
+}
+
+#[derive(Debug, Default)]
+struct Summary {
+    inserted: u64,
+    updated: u64,
+    deleted: u64,
+    copied_groups: u64,
+    rewritten_groups: u64,
+}
+
+pub fn apply_main(mut args: Args) -> eyre::Result<()> {
+    let base = open_file(&args.base).wrap_err_with(|| format!("open {}", args.base))?;
+    let metadata = ArrowReaderMetadata::load(&base, Default::default())?;
+    let schema = metadata.schema().clone();
+
+    let key_idx = args
+        .key
+        .iter()
+        .map(|k| schema.index_of(k))
+        .collect::<Result<Vec<_>, _>>()?;
+    let converter = RowConverter::new(
+        key_idx
+            .iter()
+            .map(|&i| SortField::new(schema.field(i).data_type().clone()))
+            .collect(),
+    )?;
+
+    let parquet_metadata = metadata.metadata().clone();
+    let schema_descr = parquet_metadata.file_metadata().schema_descr();
+    let first_key = column_index(schema_descr, &args.key[0])
+        .ok_or_else(|| eyre!("key {} is not a leaf column", args.key[0]))?;
+
+    let changes = read_changes(
+        &args,
+        &schema,
+        &key_idx,
+        &converter,
+        &schema_descr.column(first_key),
+    )?;
+    info!("{} keys in change set", changes.ops.len());
+
+    // touched row groups are always re-encoded
+    args.writer.reencode = true;
+    let props = Arc::new(args.writer.properties(&[parquet_metadata.as_ref()]));
+    let output = File::create(&args.output)?;
+    let mut writer = SerializedFileWriter::new(
+        output,
+        parquet_metadata
+            .file_metadata()
+            .schema_descr()
+            .root_schema_ptr(),
+        props.clone(),
+    )?;
+
+    let mut summary = Summary::default();
+    let mut applied: HashSet<usize> = HashSet::new();
+
+    for (idx, rg) in parquet_metadata.row_groups().iter().enumerate() {
+        if !changes.may_touch(rg, first_key) {
+            copy_row_group(&mut writer, &base, rg)?;
+            summary.copied_groups += 1;
+            continue;
+        }
+
+        let batch = read_row_group(&base, &metadata, idx)?;
+        let keys = converter.convert_columns(&columns(&batch, &key_idx))?;
+
+        let mut keep = Vec::with_capacity(batch.num_rows());
+        let mut replaced = vec![];
+        for i in 0..batch.num_rows() {
+            match changes.ops.get(&keys.row(i).owned()) {
+                None => keep.push(true),
+                Some((op, change_idx)) => {
+                    keep.push(false);
+                    let first_seen = applied.insert(*change_idx);
+                    match op {
+                        Op::Delete => summary.deleted += 1,
+                        _ => {
+                            summary.updated += 1;
+                            if first_seen {
+                                replaced.push(*change_idx as u32);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let kept = filter_record_batch(&batch, &BooleanArray::from(keep))?;
+        let new_rows = take_record_batch(&changes.batch, &UInt32Array::from(replaced))?;
+        let batch = concat_batches(&schema, [&kept, &new_rows])?;
+        if batch.num_rows() > 0 {
+            encode_row_group(&mut writer, &props, &batch, args.writer.jobs)?;
+        }
+        summary.rewritten_groups += 1;
+    }
+
+    // changes on keys missing in the base are inserted
+    let mut inserts = vec![];
+    for (op, change_idx) in changes.ops.values() {
+        if applied.contains(change_idx) {
+            continue;
+        }
+        match op {
+            Op::Delete => debug!("delete of a missing key, ignored"),
+            Op::Update => {
+                warn!("update of a missing key, inserted");
+                inserts.push(*change_idx as u32);
+            }
+            Op::Insert => inserts.push(*change_idx as u32),
+        }
+    }
+    inserts.sort_unstable();
+    summary.inserted = inserts.len() as u64;
+
+    let inserted = take_record_batch(&changes.batch, &UInt32Array::from(inserts))?;
+    let group_size = props.max_row_group_size();
+    let mut offset = 0;
+    while offset < inserted.num_rows() {
+        let len = group_size.min(inserted.num_rows() - offset);
+        encode_row_group(
+            &mut writer,
+            &props,
+            &inserted.slice(offset, len),
+            args.writer.jobs,
+        )?;
+        offset += len;
+    }
+
+    writer.close()?;
+
+    println!(
+        "inserted {} rows, updated {} rows, deleted {} rows",
+        summary.inserted, summary.updated, summary.deleted
+    );
+    println!(
+        "copied {} row groups, rewritten {} row groups",
+        summary.copied_groups, summary.rewritten_groups
+    );
+    Ok(())
+}
+
+fn columns(batch: &RecordBatch, idx: &[usize]) -> Vec<ArrayRef> {
+    idx.iter().map(|&i| batch.column(i).clone()).collect()
+}
+
+/// read the change file, cast its columns to the base schema and index it by key.
+/// `first_key` is the parquet column of the first key in the base
+fn read_changes(
+    args: &Args,
+    schema: &SchemaRef,
+    key_idx: &[usize],
+    converter: &RowConverter,
+    first_key: &ColumnDescriptor,
+) -> eyre::Result<Changes> {
+    let file = open_file(&args.changes).wrap_err_with(|| format!("open {}", args.changes))?;
+    let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+    let change_schema = builder.schema().clone();
+    let batches = builder.build()?.collect::<Result<Vec<_>, _>>()?;
+    let raw = concat_batches(&change_schema, &batches)?;
+
+    let op_values = cast(
+        raw.column(change_schema.index_of(&args.op_column)?),
+        &DataType::Utf8,
+    )?;
+    let op_values = op_values.as_string::<i32>();
+
+    let projected = schema
+        .fields()
+        .iter()
+        .map(|f| {
+            let idx = change_schema
+                .index_of(f.name())
+                .wrap_err_with(|| format!("column {} missing in change file", f.name()))?;
+            Ok(cast(raw.column(idx), f.data_type())?)
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+    let batch = RecordBatch::try_new(schema.clone(), projected)?;
+
+    let keys = converter.convert_columns(&columns(&batch, key_idx))?;
+    let mut ops = HashMap::new();
+    for i in 0..batch.num_rows() {
+        let op = match op_values.is_valid(i).then(|| op_values.value(i)) {
+            Some(v) if v == args.insert => Op::Insert,
+            Some(v) if v == args.update => Op::Update,
+            Some(v) if v == args.delete => Op::Delete,
+            v => return Err(eyre!("unknown op {v:?} at change row {i}")),
+        };
+        // the last change of a key wins
+        ops.insert(keys.row(i).owned(), (op, i));
+    }
+
+    // in the units of the base statistics
+    let first_keys = stat_values(batch.column(key_idx[0]), first_key).and_then(|values| {
+        let mut values = values.into_iter().collect::<Option<Vec<_>>>()?;
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        Some(values)
+    });
+    if first_keys.is_none() {
+        warn!(
+            "can not prune row groups on key {}, all of them are rewritten",
+            args.key[0]
+        );
+    }
+
+    Ok(Changes {
+        batch,
+        ops,
+        first_keys,
+    })
+}
+
+impl Changes {
+    /// whether any change key may fall into row group `rg`, judged from the
+    /// statistics of the first key column
+    fn may_touch(&self, rg: &RowGroupMetaData, first_key: usize) -> bool {
+        let (Some(values), Some((min, max))) = (&self.first_keys, min_max(rg.column(first_key)))
+        else {
+            return true;
+        };
+        let start = values.partition_point(|v| v < &min);
+        values.get(start).map_or(false, |v| v <= &max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{Date64Array, Int64Array, StringArray, TimestampSecondArray};
+    use arrow::datatypes::Int64Type;
+    use parquet::arrow::ArrowWriter;
+    use parquet::file::properties::WriterProperties;
+
+    use super::*;
+    use crate::cmd::utils::TempDir;
+
+    fn write_file(path: &std::path::Path, columns: Vec<(&str, ArrayRef)>) {
+        let batch = RecordBatch::try_from_iter(columns).unwrap();
+        let props = WriterProperties::builder()
+            .set_max_row_group_size(4)
+            .build();
+        let mut writer =
+            ArrowWriter::try_new(File::create(path).unwrap(), batch.schema(), Some(props)).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+    }
+
+    #[test]
+    fn test_apply() {
+        let dir = TempDir::new(std::env::temp_dir(), "pp-test").unwrap();
+        let base = dir.path().join("base.parquet");
+        let changes = dir.path().join("changes.parquet");
+        let output = dir.path().join("output.parquet");
+
+        // three row groups of four rows, key 1 is duplicated in the first one
+        let ids = vec![0, 1, 1, 2, 4, 5, 6, 7, 8, 9, 10, 11];
+        let names = ids.iter().map(|i| format!("n{i}")).collect::<Vec<_>>();
+        write_file(
+            &base,
+            vec![
+                ("id", Arc::new(Int64Array::from(ids)) as ArrayRef),
+                ("name", Arc::new(StringArray::from(names))),
+            ],
+        );
+        write_file(
+            &changes,
+            vec![
+                (
+                    "id",
+                    Arc::new(Int64Array::from(vec![1, 5, 100, 50, 60])) as ArrayRef,
+                ),
+                (
+                    "name",
+                    Arc::new(StringArray::from(vec!["u1", "", "i100", "u50", ""])),
+                ),
+                (
+                    "op",
+                    Arc::new(StringArray::from(vec!["U", "D", "I", "U", "D"])),
+                ),
+            ],
+        );
+
+        let args = Args::parse_from([
+            "apply",
+            "--output",
+            output.to_str().unwrap(),
+            "--key",
+            "id",
+            base.to_str().unwrap(),
+            changes.to_str().unwrap(),
+        ]);
+        apply_main(args).unwrap();
+
+        let reader =
+            ParquetRecordBatchReaderBuilder::try_new(File::open(&output).unwrap()).unwrap();
+        // the first two row groups are rewritten, the last is copied and the
+        // inserts follow in a new one
+        assert_eq!(reader.metadata().num_row_groups(), 4);
+        let batches = reader
+            .build()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let batch = concat_batches(&batches[0].schema(), &batches).unwrap();
+
+        let ids = batch.column(0).as_primitive::<Int64Type>();
+        // the update of the duplicated key 1 is written once
+        assert_eq!(
+            ids.values().to_vec(),
+            vec![0, 2, 1, 4, 6, 7, 8, 9, 10, 11, 100, 50]
+        );
+        let names = batch.column(1).as_string::<i32>();
+        assert_eq!(names.value(2), "u1");
+        assert_eq!(names.value(6), "n8");
+        assert_eq!(names.value(10), "i100");
+        assert_eq!(names.value(11), "u50");
+    }
+
+    #[test]
+    fn test_apply_time_keys() {
+        // parquet keeps Date64 as days, and other writers seconds as milliseconds
+        let day = 86_400_000;
+        let keys: Vec<(ArrayRef, ArrayRef)> = vec![
+            (
+                Arc::new(TimestampSecondArray::from((0..12).collect::<Vec<_>>())),
+                Arc::new(TimestampSecondArray::from(vec![9, 100])),
+            ),
+            (
+                Arc::new(Date64Array::from(
+                    (0..12).map(|d| d * day).collect::<Vec<_>>(),
+                )),
+                Arc::new(Date64Array::from(vec![9 * day, 100 * day])),
+            ),
+        ];
+        for (base_keys, change_keys) in keys {
+            let dir = TempDir::new(std::env::temp_dir(), "pp-test").unwrap();
+            let base = dir.path().join("base.parquet");
+            let changes = dir.path().join("changes.parquet");
+            let output = dir.path().join("output.parquet");
+
+            let names = (0..12).map(|i| format!("n{i}")).collect::<Vec<_>>();
+            write_file(
+                &base,
+                vec![
+                    ("t", base_keys),
+                    ("name", Arc::new(StringArray::from(names))),
+                ],
+            );
+            write_file(
+                &changes,
+                vec![
+                    ("t", change_keys),
+                    ("name", Arc::new(StringArray::from(vec!["u9", "i100"]))),
+                    ("op", Arc::new(StringArray::from(vec!["U", "I"]))),
+                ],
+            );
+
+            let args = Args::parse_from([
+                "apply",
+                "--output",
+                output.to_str().unwrap(),
+                "--key",
+                "t",
+                base.to_str().unwrap(),
+                changes.to_str().unwrap(),
+            ]);
+            apply_main(args).unwrap();
+
+            let reader =
+                ParquetRecordBatchReaderBuilder::try_new(File::open(&output).unwrap()).unwrap();
+            let batches = reader
+                .build()
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let batch = concat_batches(&batches[0].schema(), &batches).unwrap();
+            let names = batch
+                .column(1)
+                .as_string::<i32>()
+                .iter()
+                .flatten()
+                .collect::<Vec<_>>();
+            assert_eq!(names.len(), 13);
+            assert!(names.contains(&"u9") && !names.contains(&"n9"));
+            assert!(names.contains(&"i100"));
+        }
+    }
+}
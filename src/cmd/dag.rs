@@ -0,0 +1,75 @@
+/// order of the nodes so that each comes after the nodes it depends on, else
+/// in listed order. `deps[i]` are the indexes node `i` depends on
+pub fn topo_order(names: &[String], deps: &[Vec<usize>]) -> Result<Vec<usize>, String> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        New,
+        Visiting,
+        Done,
+    }
+
+    fn visit(
+        node: usize,
+        names: &[String],
+        deps: &[Vec<usize>],
+        states: &mut [State],
+        path: &mut Vec<usize>,
+        order: &mut Vec<usize>,
+    ) -> Result<(), String> {
+        match states[node] {
+            State::Done => return Ok(()),
+            State::Visiting => {
+                let start = path.iter().position(|&n| n == node).unwrap_or(0);
+                let cycle = path[start..]
+                    .iter()
+                    .chain([&node])
+                    .map(|&n| names[n].as_str())
+                    .collect::<Vec<_>>();
+                return Err(format!("cycle {}", cycle.join(" -> ")));
+            }
+            State::New => {}
+        }
+        states[node] = State::Visiting;
+        path.push(node);
+        for &dep in &deps[node] {
+            visit(dep, names, deps, states, path, order)?;
+        }
+        path.pop();
+        states[node] = State::Done;
+        order.push(node);
+        Ok(())
+    }
+
+    let mut states = vec![State::New; names.len()];
+    let mut order = Vec::with_capacity(names.len());
+    for node in 0..names.len() {
+        visit(node, names, deps, &mut states, &mut vec![], &mut order)?;
+    }
+    Ok(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_topo_order() {
+        let n = names(&["joined", "cleaned", "daily"]);
+        let deps = vec![vec![2, 1], vec![], vec![1]];
+        assert_eq!(topo_order(&n, &deps), Ok(vec![1, 2, 0]));
+    }
+
+    #[test]
+    fn test_topo_order_cycle() {
+        let n = names(&["a", "b", "c"]);
+        let deps = vec![vec![1], vec![2], vec![0]];
+        assert_eq!(
+            topo_order(&n, &deps),
+            Err("cycle a -> b -> c -> a".to_owned())
+        );
+    }
+}
@@ -0,0 +1,524 @@
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::str::FromStr;
+
+use arrow::array::UInt32Array;
+use arrow::compute::take_record_batch;
+use arrow::datatypes::SchemaRef;
+use arrow::row::{OwnedRow, Row, RowConverter, SortField};
+use arrow_array::{ArrayRef, RecordBatch};
+use eyre::eyre;
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::file::metadata::ParquetMetaData;
+
+use crate::cmd::stats::{column_index, min_max};
+use crate::cmd::utils::TempDir;
+
+/// which row to keep among rows sharing a key
+#[derive(Debug, Clone, PartialEq)]
+pub enum Keep {
+    First,
+    Last,
+    /// the row with the largest value of a column
+    Max(String),
+}
+
+impl FromStr for Keep {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(Keep::First),
+            "last" => Ok(Keep::Last),
+            v => match v.strip_prefix("max(").and_then(|x| x.strip_suffix(')')) {
+                Some(col) if !col.trim().is_empty() => Ok(Keep::Max(col.trim().to_owned())),
+                _ => Err(format!(
+                    "unknown keep strategy {v}, expect first, last or max(<col>)"
+                )),
+            },
+        }
+    }
+}
+
+/// drops rows with duplicate keys from a sequence of parquet files
+pub struct Dedupe {
+    schema: SchemaRef,
+    keys: Vec<usize>,
+    value: Option<usize>,
+    keep: Keep,
+}
+
+enum Candidate {
+    /// row of the batch being processed
+    InBatch(usize),
+    /// row carried over from a previous batch
+    Carried(RecordBatch),
+}
+
+struct Pending {
+    key: OwnedRow,
+    value: Option<OwnedRow>,
+    row: Candidate,
+}
+
+impl Dedupe {
+    pub fn try_new(schema: SchemaRef, keys: &[String], keep: Keep) -> eyre::Result<Self> {
+        let key_idx = keys
+            .iter()
+            .map(|k| schema.index_of(k))
+            .collect::<Result<Vec<_>, _>>()?;
+        let value = match &keep {
+            Keep::Max(col) => Some(schema.index_of(col)?),
+            _ => None,
+        };
+
+        Ok(Dedupe {
+            schema,
+            keys: key_idx,
+            value,
+            keep,
+        })
+    }
+
+    fn converter(&self, columns: &[usize]) -> eyre::Result<RowConverter> {
+        let fields = columns
+            .iter()
+            .map(|&i| SortField::new(self.schema.field(i).data_type().clone()))
+            .collect();
+        Ok(RowConverter::new(fields)?)
+    }
+
+    fn columns(batch: &RecordBatch, columns: &[usize]) -> Vec<ArrayRef> {
+        columns.iter().map(|&i| batch.column(i).clone()).collect()
+    }
+
+    /// whether a row with `new` value replaces the kept one with `current`
+    fn replaces(&self, current: Option<Row>, new: Option<Row>) -> bool {
+        match self.keep {
+            Keep::First => false,
+            Keep::Last => true,
+            Keep::Max(_) => new > current,
+        }
+    }
+
+    /// dedupe inputs sorted on the keys in one pass, returns the number of removed rows
+    pub fn sorted<E>(&self, sources: &[File], emit: &mut E) -> eyre::Result<u64>
+    where
+        E: FnMut(&RecordBatch) -> eyre::Result<()>,
+    {
+        let key_conv = self.converter(&self.keys)?;
+        let value_conv = self.value.map(|v| self.converter(&[v])).transpose()?;
+
+        let mut pending: Option<Pending> = None;
+        let mut removed = 0;
+
+        for_each_batch(sources, |batch| {
+            let keys = key_conv.convert_columns(&Self::columns(&batch, &self.keys))?;
+            let values = match (&value_conv, self.value) {
+                (Some(conv), Some(v)) => Some(conv.convert_columns(&[batch.column(v).clone()])?),
+                _ => None,
+            };
+
+            let mut take = vec![];
+            for i in 0..batch.num_rows() {
+                let key = keys.row(i);
+                let value = values.as_ref().map(|v| v.row(i));
+                let same = matches!(&pending, Some(p) if p.key.row() == key);
+                if same {
+                    removed += 1;
+                    let p = pending.as_mut().unwrap();
+                    if self.replaces(p.value.as_ref().map(|v| v.row()), value) {
+                        p.row = Candidate::InBatch(i);
+                        p.value = value.map(|v| v.owned());
+                    }
+                    continue;
+                }
+
+                if let Some(p) = pending.take() {
+                    match p.row {
+                        Candidate::InBatch(j) => take.push(j as u32),
+                        Candidate::Carried(b) => emit(&b)?,
+                    }
+                }
+                pending = Some(Pending {
+                    key: key.owned(),
+                    value: value.map(|v| v.owned()),
+                    row: Candidate::InBatch(i),
+                });
+            }
+
+            // the last key may continue in the next batch
+            if let Some(p) = &mut pending {
+                if let Candidate::InBatch(j) = p.row {
+                    p.row = Candidate::Carried(batch.slice(j, 1));
+                }
+            }
+            if !take.is_empty() {
+                emit(&take_record_batch(&batch, &UInt32Array::from(take))?)?;
+            }
+            Ok(())
+        })?;
+
+        if let Some(Pending {
+            row: Candidate::Carried(b),
+            ..
+        }) = pending
+        {
+            emit(&b)?;
+        }
+        Ok(removed)
+    }
+
+    /// dedupe unsorted inputs with a hash table of at most `max_keys` keys,
+    /// spilling to `partitions` files in a new directory below `tmp_dir` when
+    /// there are more keys. Returns the number of removed rows.
+    pub fn hashed<E>(
+        &self,
+        sources: &[File],
+        max_keys: usize,
+        tmp_dir: &Path,
+        partitions: usize,
+        emit: &mut E,
+    ) -> eyre::Result<u64>
+    where
+        E: FnMut(&RecordBatch) -> eyre::Result<()>,
+    {
+        if let Some((winners, removed)) = self.winners(sources, max_keys)? {
+            self.emit_rows(sources, &winners, emit)?;
+            return Ok(removed);
+        }
+
+        info!("more than {max_keys} distinct keys, spill to {partitions} partitions");
+        let spill_dir = TempDir::new(tmp_dir, ".pp-spill")?;
+        let spilled = self.spill(sources, spill_dir.path(), partitions)?;
+
+        let mut removed = 0;
+        for part in &spilled {
+            let part = std::slice::from_ref(part);
+            let (winners, r) = self.winners(part, max_keys)?.ok_or_else(|| {
+                eyre!("spill partition has more than {max_keys} keys, raise --spill-partitions")
+            })?;
+            self.emit_rows(part, &winners, emit)?;
+            removed += r;
+        }
+        Ok(removed)
+    }
+
+    /// sorted global indices of the rows to keep and the number of removed rows,
+    /// None if there are more than `max_keys` keys
+    fn winners(&self, sources: &[File], max_keys: usize) -> eyre::Result<Option<(Vec<u64>, u64)>> {
+        let key_conv = self.converter(&self.keys)?;
+        let value_conv = self.value.map(|v| self.converter(&[v])).transpose()?;
+
+        let mut seen: HashMap<OwnedRow, (u64, Option<OwnedRow>)> = HashMap::new();
+        let mut offset = 0u64;
+        let mut overflow = false;
+
+        for_each_batch(sources, |batch| {
+            if overflow {
+                return Ok(());
+            }
+            let keys = key_conv.convert_columns(&Self::columns(&batch, &self.keys))?;
+            let values = match (&value_conv, self.value) {
+                (Some(conv), Some(v)) => Some(conv.convert_columns(&[batch.column(v).clone()])?),
+                _ => None,
+            };
+
+            for i in 0..batch.num_rows() {
+                let value = values.as_ref().map(|v| v.row(i));
+                let global = offset + i as u64;
+                match seen.entry(keys.row(i).owned()) {
+                    Entry::Occupied(mut e) => {
+                        if self.replaces(e.get().1.as_ref().map(|v| v.row()), value) {
+                            *e.get_mut() = (global, value.map(|v| v.owned()));
+                        }
+                    }
+                    Entry::Vacant(e) => {
+                        e.insert((global, value.map(|v| v.owned())));
+                    }
+                }
+                if seen.len() > max_keys {
+                    overflow = true;
+                    return Ok(());
+                }
+            }
+            offset += batch.num_rows() as u64;
+            Ok(())
+        })?;
+
+        if overflow {
+            return Ok(None);
+        }
+        let removed = offset - seen.len() as u64;
+        let mut winners = seen.into_values().map(|(row, _)| row).collect::<Vec<_>>();
+        winners.sort_unstable();
+        Ok(Some((winners, removed)))
+    }
+
+    fn emit_rows<E>(&self, sources: &[File], winners: &[u64], emit: &mut E) -> eyre::Result<()>
+    where
+        E: FnMut(&RecordBatch) -> eyre::Result<()>,
+    {
+        let mut offset = 0u64;
+        let mut next = winners.iter().peekable();
+        for_each_batch(sources, |batch| {
+            let end = offset + batch.num_rows() as u64;
+            let mut take = vec![];
+            while let Some(&&row) = next.peek() {
+                if row >= end {
+                    break;
+                }
+                take.push((row - offset) as u32);
+                next.next();
+            }
+            offset = end;
+            if !take.is_empty() {
+                emit(&take_record_batch(&batch, &UInt32Array::from(take))?)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// partition the rows of `sources` by key hash into files below `dir`
+    fn spill(&self, sources: &[File], dir: &Path, partitions: usize) -> eyre::Result<Vec<File>> {
+        let mut writers = (0..partitions)
+            .map(|i| {
+                let path = dir.join(format!("spill_{i:04}.parquet"));
+                let writer = ArrowWriter::try_new(File::create(&path)?, self.schema.clone(), None)?;
+                Ok((path, writer))
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+
+        let key_conv = self.converter(&self.keys)?;
+        for_each_batch(sources, |batch| {
+            let keys = key_conv.convert_columns(&Self::columns(&batch, &self.keys))?;
+            let mut parts = vec![vec![]; partitions];
+            for i in 0..batch.num_rows() {
+                let mut hasher = DefaultHasher::new();
+                keys.row(i).hash(&mut hasher);
+                parts[(hasher.finish() % partitions as u64) as usize].push(i as u32);
+            }
+            for (idx, part) in parts.into_iter().enumerate() {
+                if !part.is_empty() {
+                    writers[idx]
+                        .1
+                        .write(&take_record_batch(&batch, &UInt32Array::from(part))?)?;
+                }
+            }
+            Ok(())
+        })?;
+
+        writers
+            .into_iter()
+            .map(|(path, writer)| {
+                writer.close()?;
+                Ok(File::open(path)?)
+            })
+            .collect()
+    }
+}
+
+fn for_each_batch<F>(sources: &[File], mut f: F) -> eyre::Result<()>
+where
+    F: FnMut(RecordBatch) -> eyre::Result<()>,
+{
+    for source in sources {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(source.try_clone()?)?.build()?;
+        for batch in reader {
+            f(batch?)?;
+        }
+    }
+    Ok(())
+}
+
+/// whether the row groups of `inputs`, in order, are sorted ascending on `keys`,
+/// judged from their sorting columns and statistics
+pub fn is_sorted_on(inputs: &[&ParquetMetaData], keys: &[String]) -> bool {
+    let Some(first) = inputs.first() else {
+        return true;
+    };
+    let schema = first.file_metadata().schema_descr();
+    let Some(key_idx) = keys
+        .iter()
+        .map(|k| column_index(schema, k))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return false;
+    };
+
+    let mut prev_max = None;
+    for rg in inputs.iter().flat_map(|m| m.row_groups()) {
+        let sorted = rg.sorting_columns().map_or(false, |cols| {
+            cols.len() >= key_idx.len()
+                && cols
+                    .iter()
+                    .zip(&key_idx)
+                    .all(|(c, &k)| c.column_idx as usize == k && !c.descending)
+        });
+        if !sorted {
+            return false;
+        }
+
+        let Some((min, max)) = min_max(rg.column(key_idx[0])) else {
+            return false;
+        };
+        if let Some(prev) = prev_max {
+            // equal leading keys only keep the order for single column keys
+            let ordered = match key_idx.len() {
+                1 => prev <= min,
+                _ => prev < min,
+            };
+            if !ordered {
+                return false;
+            }
+        }
+        prev_max = Some(max);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{AsArray, Int64Array};
+    use arrow::compute::concat_batches;
+    use arrow::datatypes::{DataType, Field, Int64Type, Schema};
+
+    use super::*;
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("k", DataType::Int64, false),
+            Field::new("v", DataType::Int64, false),
+        ]))
+    }
+
+    /// write rows `(keys[i], values[i])` to a parquet file in `dir`
+    fn write_file(dir: &TempDir, name: &str, keys: Vec<i64>, values: Vec<i64>) -> File {
+        let path = dir.path().join(name);
+        let batch = RecordBatch::try_new(
+            schema(),
+            vec![
+                Arc::new(Int64Array::from(keys)),
+                Arc::new(Int64Array::from(values)),
+            ],
+        )
+        .unwrap();
+        let mut writer =
+            ArrowWriter::try_new(File::create(&path).unwrap(), schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        File::open(path).unwrap()
+    }
+
+    /// (key, value) pairs emitted by `run`, sorted by key
+    fn collect<F>(run: F) -> (Vec<(i64, i64)>, u64)
+    where
+        F: FnOnce(&mut dyn FnMut(&RecordBatch) -> eyre::Result<()>) -> eyre::Result<u64>,
+    {
+        let mut batches = vec![];
+        let removed = run(&mut |b: &RecordBatch| {
+            batches.push(b.clone());
+            Ok(())
+        })
+        .unwrap();
+        let batch = concat_batches(&schema(), &batches).unwrap();
+        let keys = batch.column(0).as_primitive::<Int64Type>();
+        let values = batch.column(1).as_primitive::<Int64Type>();
+        let mut rows = keys
+            .values()
+            .iter()
+            .copied()
+            .zip(values.values().iter().copied())
+            .collect::<Vec<_>>();
+        rows.sort_unstable();
+        (rows, removed)
+    }
+
+    #[test]
+    fn test_dedupe_sorted() {
+        let dir = TempDir::new(std::env::temp_dir(), "pp-test").unwrap();
+        // three rows per key, key 341 spans the first batch boundary at row 1024
+        // and the last key of the first file continues in the second
+        let sources = [
+            write_file(
+                &dir,
+                "a.parquet",
+                (0..3000).map(|i| i / 3).collect(),
+                (0..3000).collect(),
+            ),
+            write_file(
+                &dir,
+                "b.parquet",
+                (3000..3300).map(|i| (i - 1) / 3).collect(),
+                (3000..3300).collect(),
+            ),
+        ];
+
+        let dedupe = Dedupe::try_new(schema(), &["k".to_owned()], Keep::Last).unwrap();
+        let (rows, removed) = collect(|mut emit| dedupe.sorted(&sources, &mut emit));
+
+        assert_eq!(rows.len(), 1100);
+        assert_eq!(removed, 2200);
+        assert_eq!(rows[341], (341, 1025));
+        assert_eq!(rows[999], (999, 3000));
+        assert_eq!(rows[1099], (1099, 3299));
+    }
+
+    #[test]
+    fn test_dedupe_hashed_spill() {
+        let dir = TempDir::new(std::env::temp_dir(), "pp-test").unwrap();
+        // keys repeat across the files and the batches of the first file
+        let sources = [
+            write_file(
+                &dir,
+                "a.parquet",
+                (0..3000).map(|i| i % 1000).collect(),
+                (0..3000).collect(),
+            ),
+            write_file(
+                &dir,
+                "b.parquet",
+                (0..1000).rev().collect(),
+                (0..1000).map(|i| 5000 - i).collect(),
+            ),
+        ];
+
+        let dedupe =
+            Dedupe::try_new(schema(), &["k".to_owned()], Keep::Max("v".to_owned())).unwrap();
+        for max_keys in [10_000, 300] {
+            let (rows, removed) =
+                collect(|mut emit| dedupe.hashed(&sources, max_keys, dir.path(), 8, &mut emit));
+            assert_eq!(removed, 3000);
+            let expected = (0..1000)
+                .map(|k| (k, (2000 + k).max(4001 + k)))
+                .collect::<Vec<_>>();
+            assert_eq!(rows, expected);
+        }
+        // only the written inputs are left, the spill directory is removed
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_parse_keep() {
+        assert_eq!("first".parse::<Keep>(), Ok(Keep::First));
+        assert_eq!("last".parse::<Keep>(), Ok(Keep::Last));
+        assert_eq!(
+            "max(update_time)".parse::<Keep>(),
+            Ok(Keep::Max("update_time".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_parse_keep_bad_value() {
+        assert!("max()".parse::<Keep>().is_err());
+        assert!("min(update_time)".parse::<Keep>().is_err());
+    }
+}
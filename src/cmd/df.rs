@@ -1,24 +1,61 @@
-use std::collections::HashMap;
-use std::fs;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Instant;
 
+use async_std::stream::StreamExt;
 use async_std::task;
 use clap::Parser;
-use datafusion::arrow::datatypes::{DataType, Field, SchemaBuilder, TimeUnit};
+use datafusion::arrow::array::{Array, UInt64Array};
+use datafusion::arrow::csv;
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaBuilder};
+use datafusion::arrow::ipc::writer::{FileWriter, StreamWriter};
+use datafusion::arrow::json::LineDelimitedWriter;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::common::file_options::csv_writer::CsvWriterOptions;
+use datafusion::common::file_options::json_writer::JsonWriterOptions;
+use datafusion::common::file_options::parquet_writer::ParquetWriterOptions;
+use datafusion::common::file_options::FileTypeWriterOptions;
+use datafusion::common::parsers::CompressionTypeVariant;
+use datafusion::common::{Column, GetExt};
 use datafusion::dataframe::DataFrameWriteOptions;
-use datafusion::parquet::basic::{Compression, Encoding, ZstdLevel};
-use datafusion::parquet::file::properties::{EnabledStatistics, WriterProperties, WriterVersion};
+use datafusion::datasource::file_format::csv::CsvFormat;
+use datafusion::datasource::file_format::file_compression_type::FileCompressionType;
+use datafusion::datasource::file_format::json::JsonFormat;
+use datafusion::datasource::file_format::parquet::ParquetFormat;
+use datafusion::datasource::file_format::FileFormat;
+use datafusion::datasource::listing::{
+    ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl,
+};
+use datafusion::parquet::arrow::ArrowWriter;
+use datafusion::parquet::basic::{Compression, Encoding};
+use datafusion::parquet::file::properties::{
+    EnabledStatistics, WriterProperties, WriterPropertiesBuilder, WriterVersion,
+};
+use datafusion::parquet::format::{KeyValue, SortingColumn};
 use datafusion::parquet::schema::types::ColumnPath;
 use datafusion::prelude::{
-    AvroReadOptions, CsvReadOptions, NdJsonReadOptions, ParquetReadOptions, SessionConfig,
-    SessionContext,
+    cast, AvroReadOptions, CsvReadOptions, DataFrame, Expr, NdJsonReadOptions, ParquetReadOptions,
+    SessionConfig, SessionContext,
 };
-use eyre::{Error, OptionExt};
-use log::{debug, info, warn};
-use object_store::aws::AmazonS3Builder;
-use serde::{Deserialize, Serialize};
-use url::Url;
+use eyre::Error;
+use log::{debug, error, info, warn};
+use object_store::path::Path as ObjectPath;
+use object_store::ObjectStore;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+
+use crate::cmd::dag::topo_order;
+use crate::cmd::listing::Listing;
+use crate::cmd::pattern::{matching, ColumnPattern};
+use crate::cmd::schema::parse_type;
+use crate::cmd::store::{StoreConfig, StoreRef, Stores};
+use crate::cmd::vars::{parse_var, Vars};
+use crate::cmd::writer::{parse_compression, parse_encoding};
 
 #[derive(Parser, Debug)]
 /// run sql with datafusion and write the result to a parquet file
@@ -35,12 +72,31 @@ pub struct Args {
 
     #[arg(long, help = "target file")]
     sink: Option<String>,
+
+    #[arg(long)]
+    /// job to run, all jobs of the config by default
+    only: Vec<String>,
+
+    #[arg(long)]
+    /// print the stages and jobs in run order with their tables, run nothing
+    plan: bool,
+
+    #[arg(long)]
+    /// write the jobs with their rows and files as json to this path
+    report: Option<String>,
+
+    #[arg(long = "set", value_parser = parse_var)]
+    /// variable for ${VAR} in the config, as key=value, overrides the environment
+    vars: Vec<(String, String)>,
 }
 
 pub(crate) fn df_main(args: Args) -> eyre::Result<()> {
     let contents = fs::read_to_string(args.config).expect("Should have been able to read the file");
 
-    let cfg: DFConfig = serde_yaml::from_str::<DFConfig>(&contents)?;
+    let mut value = serde_yaml::from_str::<serde_yaml::Value>(&contents)?;
+    Vars::new(&args.vars).substitute(&mut value, &contents)?;
+    // back to text, plain scalars like `statistic: false` are read into strings from there
+    let cfg: DFConfig = serde_yaml::from_str::<DFConfig>(&serde_yaml::to_string(&value)?)?;
 
     debug!("{:?}", cfg);
 
@@ -49,15 +105,11 @@ pub(crate) fn df_main(args: Args) -> eyre::Result<()> {
         return Err(Error::msg("no sql query provided"));
     }
 
-    // error if the args- or default query not in config file
-    if args.query.is_some() {
-        if !cfg.query.contains_key(args.query.clone().unwrap().as_str()) {
-            return Err(Error::msg("query not found in config"));
-        }
-    } else {
-        if !cfg.query.contains_key("default") {
-            return Err(Error::msg("no default query config nor arguments"));
-        }
+    let jobs = select_jobs(&cfg, args.query, args.sink, &args.only)?;
+    let plan = Plan::new(&cfg, &jobs)?;
+    if args.plan {
+        plan.print(&cfg, &jobs);
+        return Ok(());
     }
 
     // // error if source number in args and config doesn't match.
@@ -74,209 +126,981 @@ pub(crate) fn df_main(args: Args) -> eyre::Result<()> {
         .with_batch_size(6666);
 
     let ctx = SessionContext::new_with_config(config);
+    let mut stores = Stores::new(cfg.stores.clone());
 
     for (idx, src) in cfg.source.iter().enumerate() {
-        let path = match args.source.get(idx) {
-            None => src.path.clone(),
-            Some(v) => v.clone(),
+        let paths = match args.source.get(idx) {
+            None => src.path.paths(),
+            Some(v) => vec![v.clone()],
         };
+        for path in &paths {
+            stores.register(&ctx, path, src.store.as_ref())?;
+        }
 
         match src.format.as_str() {
-            "parquet" => {
-                info!("reginster parquet {}", src.name.as_str());
-                task::block_on(ctx.register_parquet(
-                    src.name.as_str(),
-                    &format!("{}", path),
-                    ParquetReadOptions::default(),
-                ))?;
+            "parquet" => register_parquet(&ctx, src, &paths)?,
+            "csv" => register_csv(&ctx, src, &paths)?,
+            "json" => register_json(&ctx, src, &paths)?,
+            "avro" => register_avro(&ctx, src, &paths)?,
+            v => {
+                return Err(Error::msg(format!(
+                    "unknown format {v} of source {}, expect parquet, csv, json or avro",
+                    src.name
+                )))
             }
-            "csv" => {
-                let mut opt = CsvReadOptions::default();
-                let mut sbuilder = SchemaBuilder::new();
+        }
+    }
 
-                opt.has_header = src.header.unwrap_or_else(|| false);
-                let schema = src.schema.clone().unwrap_or_default();
+    // parquet stages are written here and removed after the jobs
+    let stages_dir = std::env::temp_dir().join(format!("pp-df-{}", uuid::Uuid::new_v4()));
+    let result = run_stages(&ctx, &cfg, &plan, &stages_dir);
+    if result.is_err() && stages_dir.exists() {
+        fs::remove_dir_all(&stages_dir)?;
+    }
+    result?;
 
-                for col_def in schema {
-                    let f = build_fields(&col_def);
-                    sbuilder.push(f);
-                }
+    let mut reports = vec![];
+    for job in &jobs {
+        info!("run job {}", job.name);
+        let start = Instant::now();
+        let result = run_job(&ctx, &mut stores, &cfg.query[&job.query], &job.sink);
+        if let Err(e) = &result {
+            error!("job {} failed: {e}", job.name);
+        }
+        reports.push(JobReport {
+            name: job.name.clone(),
+            target: job.sink.path.clone(),
+            seconds: start.elapsed().as_secs_f64(),
+            result,
+        });
+    }
 
-                let csv_schema = Arc::new(sbuilder.finish());
+    if stages_dir.exists() {
+        fs::remove_dir_all(&stages_dir)?;
+    }
 
-                opt.schema = Option::from(csv_schema.as_ref());
-                //println!("{}","Int32".parse::<DataType>());
+    print_summary(&reports);
+    if let Some(path) = &args.report {
+        write_report(path, &reports)?;
+    }
+    let failed = reports.iter().filter(|r| r.result.is_err()).count();
+    if failed > 0 {
+        return Err(Error::msg(format!(
+            "{failed} of {} jobs failed",
+            reports.len()
+        )));
+    }
+    Ok(())
+}
 
-                task::block_on(ctx.register_csv(src.name.as_str(), &format!("{}", path), opt))?;
+/// the jobs to run, the `jobs` of the config or one of `--query` into the sink
+fn select_jobs(
+    cfg: &DFConfig,
+    query: Option<String>,
+    sink_path: Option<String>,
+    only: &[String],
+) -> eyre::Result<Vec<Job>> {
+    if cfg.jobs.is_empty() {
+        if !only.is_empty() {
+            return Err(Error::msg("--only selects jobs, the config has none"));
+        }
+        // error if the args- or default query not in config file
+        if query.is_some() {
+            if !cfg.query.contains_key(query.clone().unwrap().as_str()) {
+                return Err(Error::msg("query not found in config"));
             }
-            "json" => {
-                task::block_on(ctx.register_json(
-                    src.name.as_str(),
-                    &format!("{}", path),
-                    NdJsonReadOptions::default(),
-                ))?;
+        } else {
+            if !cfg.query.contains_key("default") {
+                return Err(Error::msg("no default query config nor arguments"));
             }
-            "avro" => {
-                task::block_on(ctx.register_avro(
-                    src.name.as_str(),
-                    &format!("{}", path),
-                    AvroReadOptions::default(),
-                ))?;
+        }
+        let mut sink = cfg
+            .sink
+            .clone()
+            .ok_or_else(|| Error::msg("no sink nor jobs in config"))?;
+        if let Some(path) = sink_path {
+            sink.path = path;
+        }
+        // query search order: cmd, default
+        let query_name = query.unwrap_or_else(|| format!("default"));
+        return Ok(vec![Job {
+            name: query_name.clone(),
+            query: query_name,
+            sink,
+        }]);
+    }
+
+    if query.is_some() || sink_path.is_some() {
+        return Err(Error::msg(
+            "--query and --sink are for configs without jobs, use --only",
+        ));
+    }
+    for (i, job) in cfg.jobs.iter().enumerate() {
+        if !cfg.query.contains_key(&job.query) {
+            return Err(Error::msg(format!(
+                "query {} of job {} not found in config",
+                job.query, job.name
+            )));
+        }
+        if cfg.jobs[..i].iter().any(|j| j.name == job.name) {
+            return Err(Error::msg(format!("duplicate job {}", job.name)));
+        }
+    }
+    for name in only {
+        if !cfg.jobs.iter().any(|j| j.name == *name) {
+            return Err(Error::msg(format!("job {name} not found in config")));
+        }
+    }
+    Ok(cfg
+        .jobs
+        .iter()
+        .filter(|j| only.is_empty() || only.contains(&j.name))
+        .cloned()
+        .collect())
+}
+
+/// stages in run order with the tables their queries read
+struct Plan {
+    order: Vec<usize>,
+    tables: Vec<Vec<String>>,
+    job_tables: Vec<Vec<String>>,
+}
+
+impl Plan {
+    /// order the stages the jobs need after the stages they read
+    fn new(cfg: &DFConfig, jobs: &[Job]) -> eyre::Result<Self> {
+        let names = cfg
+            .stages
+            .iter()
+            .map(|s| s.name.clone())
+            .collect::<Vec<_>>();
+        for (i, stage) in cfg.stages.iter().enumerate() {
+            if names[..i].contains(&stage.name) {
+                return Err(Error::msg(format!("duplicate stage {}", stage.name)));
             }
-            v => {
-                warn!("unknown source format {v}, skipping")
+            if cfg.source.iter().any(|s| s.name == stage.name) {
+                return Err(Error::msg(format!(
+                    "stage {} has the name of a source",
+                    stage.name
+                )));
+            }
+        }
+
+        let query_tables = |name: &str| -> eyre::Result<Vec<String>> {
+            let sql = cfg
+                .query
+                .get(name)
+                .ok_or_else(|| Error::msg(format!("query {name} not found in config")))?;
+            table_refs(sql)
+        };
+        let tables = cfg
+            .stages
+            .iter()
+            .map(|s| query_tables(&s.query))
+            .collect::<eyre::Result<Vec<_>>>()?;
+        let job_tables = jobs
+            .iter()
+            .map(|j| query_tables(&j.query))
+            .collect::<eyre::Result<Vec<_>>>()?;
+
+        let stage_deps = |tables: &[String]| {
+            tables
+                .iter()
+                .filter_map(|t| names.iter().position(|n| n == t))
+                .collect::<Vec<_>>()
+        };
+        let deps = tables.iter().map(|t| stage_deps(t)).collect::<Vec<_>>();
+        let order = topo_order(&names, &deps).map_err(|e| Error::msg(format!("stages: {e}")))?;
+
+        // stages read by the jobs, directly or through other stages
+        let mut needed = vec![false; names.len()];
+        let mut pending = job_tables
+            .iter()
+            .flat_map(|t| stage_deps(t))
+            .collect::<Vec<_>>();
+        while let Some(i) = pending.pop() {
+            if !needed[i] {
+                needed[i] = true;
+                pending.extend(&deps[i]);
             }
         }
+        let order = order.into_iter().filter(|&i| needed[i]).collect();
+
+        Ok(Plan {
+            order,
+            tables,
+            job_tables,
+        })
+    }
+
+    fn print(&self, cfg: &DFConfig, jobs: &[Job]) {
+        for &i in &self.order {
+            let stage = &cfg.stages[i];
+            println!(
+                "stage {} ({:?}, query {}) <- {}",
+                stage.name,
+                stage.cache,
+                stage.query,
+                self.tables[i].join(", ")
+            );
+        }
+        for (job, tables) in jobs.iter().zip(&self.job_tables) {
+            println!(
+                "job {} (query {}) <- {} -> {}",
+                job.name,
+                job.query,
+                tables.join(", "),
+                job.sink.path
+            );
+        }
     }
+}
+
+/// names of the tables read by `sql`
+fn table_refs(sql: &str) -> eyre::Result<Vec<String>> {
+    let state = SessionContext::new().state();
+    let statement = state.sql_to_statement(sql, "generic")?;
+    let mut tables = vec![];
+    for t in state.resolve_table_references(&statement)? {
+        let name = t.table().to_owned();
+        if !tables.contains(&name) {
+            tables.push(name);
+        }
+    }
+    Ok(tables)
+}
 
-    let mut props = WriterProperties::builder()
-        // file settings
-        .set_writer_version(WriterVersion::PARQUET_2_0)
-        .set_created_by("pp".to_owned())
-        .set_write_batch_size(16 * 1024 * 1024)
-        .set_dictionary_enabled(false)
-        .set_max_statistics_size(1024);
-
-    let file_parameters = cfg.sink.parameters;
-
-    if file_parameters.contains_key("statistic") {
-        let enable_statistic = file_parameters.get("statistic").unwrap().to_lowercase();
-        props = match enable_statistic.as_str() {
-            "false" => props.set_statistics_enabled(EnabledStatistics::None),
-            "true" => props.set_statistics_enabled(EnabledStatistics::Chunk),
-            _ => props,
-        }
-    }
-
-    if file_parameters.contains_key("max_group_size") {
-        let grout_size = match file_parameters
-            .get("max_group_size")
-            .unwrap()
-            .to_uppercase()
-            .as_str()
-            .parse::<usize>()
-        {
-            Ok(v) => v,
-            _ => {
-                warn!(
-                    "invalid max_group_size: {}, use default size",
-                    file_parameters.get("max_group_size").unwrap()
-                );
-                1000000
+/// register the stages of the plan, in order
+fn run_stages(ctx: &SessionContext, cfg: &DFConfig, plan: &Plan, dir: &Path) -> eyre::Result<()> {
+    for &i in &plan.order {
+        let stage = &cfg.stages[i];
+        info!("run stage {} ({:?})", stage.name, stage.cache);
+        let df = task::block_on(ctx.sql(&cfg.query[&stage.query]))?;
+        match stage.cache {
+            StageCache::View => {
+                ctx.register_table(stage.name.as_str(), df.into_view())?;
             }
-        };
-        props = props.set_max_row_group_size(grout_size);
+            StageCache::Memory => {
+                let df = task::block_on(df.cache())?;
+                ctx.register_table(stage.name.as_str(), df.into_view())?;
+            }
+            StageCache::Parquet => {
+                let path = format!("{}/", dir.join(&stage.name).display());
+                task::block_on(df.write_parquet(&path, DataFrameWriteOptions::new(), None))?;
+                task::block_on(ctx.register_parquet(
+                    stage.name.as_str(),
+                    &path,
+                    ParquetReadOptions::default(),
+                ))?;
+            }
+        }
     }
+    Ok(())
+}
 
-    if file_parameters.contains_key("encoding") {
-        let encoding_type = get_encoding(&file_parameters);
-        props = props.set_encoding(encoding_type);
+/// run `query` into `sink`, returns the rows and files written
+fn run_job(
+    ctx: &SessionContext,
+    stores: &mut Stores,
+    query: &str,
+    sink: &Sink,
+) -> eyre::Result<JobOutput> {
+    // dataframes keep the session settings they are created with
+    let max_rows_key = "datafusion.execution.soft_max_rows_per_output_file";
+    let df = match sink.max_rows_per_file {
+        None => task::block_on(ctx.sql(query))?,
+        Some(rows) => {
+            let default = ctx
+                .state()
+                .config()
+                .options()
+                .execution
+                .soft_max_rows_per_output_file;
+            task::block_on(ctx.sql(&format!("SET {max_rows_key} = {rows}")))?;
+            let df = task::block_on(ctx.sql(query));
+            task::block_on(ctx.sql(&format!("SET {max_rows_key} = {default}")))?;
+            df?
+        }
     };
 
-    if file_parameters.contains_key("compression") {
-        let compression_type = get_compression(&file_parameters);
-        props = props.set_compression(compression_type);
+    let output_columns = df
+        .schema()
+        .fields()
+        .iter()
+        .map(|f| f.name().clone())
+        .collect::<Vec<_>>();
+    let props = writer_properties(sink, &output_columns)?;
+
+    let target_name = sink.path.as_str();
+    let format = SinkFormat::parse(&sink.format)?;
+    let directory = !sink.partition_by.is_empty() || sink.max_rows_per_file.is_some();
+    if sink.file_prefix.is_some() && !directory {
+        return Err(Error::msg(
+            "file_prefix needs a directory output, set partition_by or max_rows_per_file",
+        ));
+    }
+    if directory && (target_name == "-" || format == SinkFormat::Arrow) {
+        return Err(Error::msg(
+            "partition_by and max_rows_per_file need a parquet, csv or json file output",
+        ));
     }
+    if target_name == "-" {
+        let out: Box<dyn Write + Send> = Box::new(std::io::stdout());
+        return write_stream(df, format, sink, props, out, true).map(JobOutput::single);
+    }
+    stores.register(ctx, target_name, sink.store.as_ref())?;
 
-    let comumn_parameters = cfg.sink.columns;
-    for cp in comumn_parameters {
-        let name = match cp.get("name") {
-            Some(v) => v.as_str(),
-            None => {
-                warn!("column name not found, skip");
-                continue;
+    let url = ListingTableUrl::parse(target_name)?;
+    let store = ctx.runtime_env().object_store(url.object_store())?;
+    let target = url.prefix().clone();
+    let existing = written_objects(store.as_ref(), &target, directory)?;
+    let marker = target.child(COMMIT_MARKER);
+    if directory && existing.contains(&marker) {
+        return Err(Error::msg(format!(
+            "an earlier commit to {target_name} did not finish, {COMMIT_MARKER} in it \
+             lists the files it added and the stale ones to remove"
+        )));
+    }
+    if !existing.is_empty() {
+        match sink.mode {
+            SinkMode::Error => {
+                return Err(Error::msg(format!(
+                    "{target_name} exists, set mode to overwrite, append or ignore"
+                )))
             }
-        };
+            SinkMode::Ignore => {
+                info!("{target_name} exists, skipped");
+                return Ok(JobOutput::skipped());
+            }
+            SinkMode::Append if !directory => {
+                return Err(Error::msg(format!(
+                    "{target_name} is a file, append needs partition_by or max_rows_per_file"
+                )))
+            }
+            SinkMode::Append | SinkMode::Overwrite => {}
+        }
+    }
 
-        if cp.contains_key("compression") {
-            let compression_type = get_compression(&cp);
-            props = props.set_column_compression(ColumnPath::from(name), compression_type);
-        }
-        if cp.contains_key("encoding") {
-            let encoding_type = get_encoding(&cp);
-            props = props.set_column_encoding(ColumnPath::from(name), encoding_type);
-        }
-        if cp.contains_key("statistic") {
-            let enable_statistic = cp.get("statistic").unwrap().to_lowercase();
-            // info!("for sttics {}, {}", name, enable_statistic);
-            match enable_statistic.as_str() {
-                "false" => {
-                    props = props.set_column_statistics_enabled(
-                        ColumnPath::from(name),
-                        EnabledStatistics::None,
-                    )
-                }
-                "true" => {
-                    props = props.set_column_statistics_enabled(
-                        ColumnPath::from(name),
-                        EnabledStatistics::Chunk,
-                    )
-                }
-                _ => {
-                    warn!("unknown statistic type {}, skip", enable_statistic);
+    // write next to the target and move the files there on success
+    let tmp_name = temp_sibling(target_name);
+    let tmp = ListingTableUrl::parse(&tmp_name)?.prefix().clone();
+    let result = write_files(ctx, df, format, sink, props, &tmp_name, directory)
+        .and_then(|rows| Ok((rows, written_objects(store.as_ref(), &tmp, directory)?)))
+        .and_then(|(rows, written)| {
+            let moves = written
+                .into_iter()
+                .map(|from| {
+                    let to = target_path(&from, &tmp, &target, sink, directory);
+                    (from, to)
+                })
+                .collect::<Vec<_>>();
+            if sink.mode == SinkMode::Append {
+                if let Some((_, to)) = moves.iter().find(|(_, to)| existing.contains(to)) {
+                    return Err(Error::msg(format!("append would replace {to}")));
                 }
             }
+            Ok((rows, moves))
+        });
+    let (rows, moves) = match result {
+        Ok(v) => v,
+        Err(e) => {
+            // leave nothing of a failed write behind
+            let cleanup = written_objects(store.as_ref(), &tmp, directory)
+                .and_then(|partial| remove_objects(store.as_ref(), &partial));
+            if let Err(c) = cleanup {
+                warn!("remove the partial output {tmp_name}: {c}");
+            }
+            remove_local_dir(&url, &tmp_name);
+            return Err(e);
         }
+    };
+
+    // files of an overwritten directory are removed once the new ones are in
+    // place, until then a marker in the directory lists both
+    let stale = match sink.mode {
+        SinkMode::Overwrite => existing
+            .into_iter()
+            .filter(|p| moves.iter().all(|(_, to)| to != p))
+            .collect::<Vec<_>>(),
+        _ => vec![],
+    };
+    let marker = (!stale.is_empty()).then_some(marker);
+    if let Some(marker) = &marker {
+        let content = json!({
+            "add": moves.iter().map(|(_, to)| to.to_string()).collect::<Vec<_>>(),
+            "remove": stale.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+        });
+        task::block_on(store.put(marker, serde_json::to_vec_pretty(&content)?.into()))?;
     }
 
-    if cfg.sink.format == "s3" {
-        let s3cfg = cfg.sink.s3.ok_or_eyre("s3 format without config")?;
-        let bucket_name = s3cfg.get("bucket").ok_or_eyre(Error::msg("no buucket"))?;
-        let region = s3cfg.get("region").ok_or_eyre(Error::msg("no region"))?;
-        let key_id = s3cfg
-            .get("access_id")
-            .ok_or_eyre(Error::msg("no access_id"))?;
-        let secret_key = s3cfg
-            .get("secret_key")
-            .ok_or_eyre(Error::msg("no secret_key"))?;
-        let endpoint = s3cfg
-            .get("endpoint")
-            .ok_or_eyre(Error::msg("no endpoint"))?;
-
-        let s3 = AmazonS3Builder::new()
-            .with_allow_http(true)
-            .with_bucket_name(bucket_name)
-            .with_region(region)
-            .with_access_key_id(key_id)
-            .with_secret_access_key(secret_key)
-            .with_endpoint(endpoint)
-            .build()?;
-
-        let path = format!("s3://{bucket_name}");
-        let s3_url = Url::parse(&path).unwrap();
-        ctx.runtime_env()
-            .register_object_store(&s3_url, Arc::new(s3));
-    }
-    // query search order: cmd, default
-    let query_name = args.query.unwrap_or_else(|| format!("default"));
-
-    let query = cfg.query.get(query_name.as_str()).unwrap();
-
-    let df = task::block_on(ctx.sql(query.as_str()))?;
-
-    let target_name = args.sink.unwrap_or_else(|| cfg.sink.path.clone());
-
-    let props = props.build();
-
-    task::block_on(
-        df.write_parquet(
-            target_name.as_str(),
-            DataFrameWriteOptions::new()
-                .with_overwrite(false)
-                .with_single_file_output(true),
-            Some(props),
+    let mut files = vec![];
+    for (from, to) in moves {
+        task::block_on(store.rename(&from, &to))?;
+        let partition = to
+            .parts()
+            .filter_map(|p| {
+                let (k, v) = p.as_ref().split_once('=')?;
+                sink.partition_by
+                    .iter()
+                    .any(|c| c == k)
+                    .then(|| (k.to_owned(), v.to_owned()))
+            })
+            .collect();
+        files.push(OutputFile {
+            path: format!("{}{to}", url.object_store().as_str()),
+            partition,
+        });
+    }
+    remove_objects(store.as_ref(), &stale)?;
+    if let Some(marker) = &marker {
+        task::block_on(store.delete(marker))?;
+    }
+    remove_local_dir(&url, &tmp_name);
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(JobOutput {
+        rows,
+        files,
+        skipped: false,
+    })
+}
+
+/// write `df` with datafusion, arrow files are written by [`write_stream`]
+fn write_files(
+    ctx: &SessionContext,
+    df: DataFrame,
+    format: SinkFormat,
+    sink: &Sink,
+    props: WriterProperties,
+    path: &str,
+    directory: bool,
+) -> eyre::Result<u64> {
+    if !sink.partition_by.is_empty() {
+        return write_partitioned(ctx, df, format, sink, props, path);
+    }
+    let options = DataFrameWriteOptions::new()
+        .with_overwrite(false)
+        .with_single_file_output(!directory);
+    let written = match format {
+        SinkFormat::Parquet => task::block_on(df.write_parquet(path, options, Some(props))),
+        SinkFormat::Csv => {
+            let builder = csv_writer(sink)?;
+            task::block_on(df.write_csv(path, options, Some(builder)))
+        }
+        SinkFormat::Json => {
+            let json = sink.json.clone().unwrap_or_default();
+            let options = match &json.compression {
+                Some(v) => options.with_compression(
+                    CompressionTypeVariant::from_str(v)
+                        .map_err(|e| Error::msg(format!("json compression {v}: {e}")))?,
+                ),
+                None => options,
+            };
+            task::block_on(df.write_json(path, options))
+        }
+        SinkFormat::Arrow => return write_arrow_file(ctx, df, path, sink, props),
+    }
+    .map_err(|e| Error::msg(format!("writing {} failed: {e}", sink.path)))?;
+    Ok(written_rows(&written))
+}
+
+/// write `df` into hive style partition directories below `path` by inserting
+/// it into a listing table partitioned by the sink columns. Partition values
+/// are written as strings
+fn write_partitioned(
+    ctx: &SessionContext,
+    df: DataFrame,
+    format: SinkFormat,
+    sink: &Sink,
+    props: WriterProperties,
+    path: &str,
+) -> eyre::Result<u64> {
+    let json_compression = || match &sink.json.clone().unwrap_or_default().compression {
+        Some(v) => CompressionTypeVariant::from_str(v)
+            .map_err(|e| Error::msg(format!("json compression {v}: {e}"))),
+        None => Ok(CompressionTypeVariant::UNCOMPRESSED),
+    };
+    let (file_format, write_options): (Arc<dyn FileFormat>, _) = match format {
+        SinkFormat::Parquet => (
+            Arc::new(ParquetFormat::default()),
+            FileTypeWriterOptions::Parquet(ParquetWriterOptions::new(props)),
         ),
-    )
-    .expect(format!("writing parquet {} failed", target_name).as_str());
+        SinkFormat::Csv => (
+            Arc::new(CsvFormat::default()),
+            FileTypeWriterOptions::CSV(CsvWriterOptions::new(
+                csv_writer(sink)?,
+                CompressionTypeVariant::UNCOMPRESSED,
+            )),
+        ),
+        SinkFormat::Json => (
+            Arc::new(JsonFormat::default()),
+            FileTypeWriterOptions::JSON(JsonWriterOptions::new(json_compression()?)),
+        ),
+        SinkFormat::Arrow => {
+            return Err(Error::msg(
+                "partition_by needs a parquet, csv or json output",
+            ))
+        }
+    };
+
+    // the table has the file columns first and the partition columns last
+    let names = df
+        .schema()
+        .fields()
+        .iter()
+        .map(|f| f.name().clone())
+        .collect::<Vec<_>>();
+    if let Some(c) = sink.partition_by.iter().find(|c| !names.contains(c)) {
+        return Err(Error::msg(format!(
+            "partition column {c} is not in the query result"
+        )));
+    }
+    let mut exprs = names
+        .iter()
+        .filter(|n| !sink.partition_by.contains(n))
+        .map(|n| Expr::Column(Column::from_name(n.as_str())))
+        .collect::<Vec<_>>();
+    let file_columns = exprs.len();
+    for c in &sink.partition_by {
+        exprs.push(cast(Expr::Column(Column::from_name(c.as_str())), DataType::Utf8).alias(c));
+    }
+    let df = df.select(exprs)?;
+    let schema = Schema::from(df.schema());
+    let file_schema = Schema::new(schema.fields()[..file_columns].to_vec());
+
+    let partition_cols = sink
+        .partition_by
+        .iter()
+        .map(|c| (c.clone(), DataType::Utf8))
+        .collect();
+    let options = ListingOptions::new(file_format)
+        .with_table_partition_cols(partition_cols)
+        .with_write_options(write_options);
+    // listing tables insert into directories, which end with a slash
+    let path = match path.ends_with('/') {
+        true => path.to_owned(),
+        false => format!("{path}/"),
+    };
+    let config = ListingTableConfig::new(ListingTableUrl::parse(&path)?)
+        .with_listing_options(options)
+        .with_schema(Arc::new(file_schema));
+
+    let table = format!("pp_sink_{}", uuid::Uuid::new_v4().simple());
+    ctx.register_table(table.as_str(), Arc::new(ListingTable::try_new(config)?))?;
+    let written = task::block_on(df.write_table(&table, DataFrameWriteOptions::new()));
+    ctx.deregister_table(table.as_str())?;
+    let written = written.map_err(|e| Error::msg(format!("writing {} failed: {e}", sink.path)))?;
+    Ok(written_rows(&written))
+}
+
+/// a hidden path next to `target` for writing before the commit
+fn temp_sibling(target: &str) -> String {
+    let trimmed = target.trim_end_matches('/');
+    let (parent, leaf) = match trimmed.rsplit_once('/') {
+        Some((parent, leaf)) => (format!("{parent}/"), leaf),
+        None => (String::new(), trimmed),
+    };
+    let slash = if target.ends_with('/') { "/" } else { "" };
+    format!("{parent}.pp-tmp-{}-{leaf}{slash}", uuid::Uuid::new_v4())
+}
+
+/// name of the file listing the changes of an unfinished overwrite of a directory
+const COMMIT_MARKER: &str = "_pp_commit.json";
+
+/// where the written object `from` below `tmp` goes in `target`
+fn target_path(
+    from: &ObjectPath,
+    tmp: &ObjectPath,
+    target: &ObjectPath,
+    sink: &Sink,
+    directory: bool,
+) -> ObjectPath {
+    if !directory {
+        return target.clone();
+    }
+    let parts = from
+        .prefix_match(tmp)
+        .map(|parts| parts.collect::<Vec<_>>())
+        .unwrap_or_default();
+    let last = parts.len().saturating_sub(1);
+    let mut to = target.clone();
+    for (i, part) in parts.into_iter().enumerate() {
+        to = match (&sink.file_prefix, i == last) {
+            (Some(prefix), true) => to.child(format!("{prefix}{}", part.as_ref())),
+            _ => to.child(part),
+        };
+    }
+    to
+}
+
+/// the object at `path`, or the objects under it for directories
+fn written_objects(
+    store: &dyn ObjectStore,
+    path: &ObjectPath,
+    directory: bool,
+) -> eyre::Result<Vec<ObjectPath>> {
+    if directory {
+        return task::block_on(async {
+            let mut objects = vec![];
+            let mut listing = store.list(Some(path));
+            while let Some(meta) = listing.next().await {
+                objects.push(meta?.location);
+            }
+            Ok(objects)
+        });
+    }
+    match task::block_on(store.head(path)) {
+        Ok(_) => Ok(vec![path.clone()]),
+        Err(object_store::Error::NotFound { .. }) => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn remove_objects(store: &dyn ObjectStore, paths: &[ObjectPath]) -> eyre::Result<()> {
+    for path in paths {
+        task::block_on(store.delete(path))?;
+    }
+    Ok(())
+}
+
+/// local stores leave the emptied directories of moved files behind
+fn remove_local_dir(url: &ListingTableUrl, path: &str) {
+    if url.scheme() == "file" && Path::new(path).is_dir() {
+        if let Err(e) = fs::remove_dir_all(path) {
+            warn!("remove {path}: {e}");
+        }
+    }
+}
+
+/// rows and files written by a job
+struct JobOutput {
+    rows: u64,
+    files: Vec<OutputFile>,
+    /// the target exists and the sink mode is ignore
+    skipped: bool,
+}
+
+impl JobOutput {
+    /// a stream without a listing of files
+    fn single(rows: u64) -> Self {
+        JobOutput {
+            rows,
+            files: vec![],
+            skipped: false,
+        }
+    }
+
+    fn skipped() -> Self {
+        JobOutput {
+            rows: 0,
+            files: vec![],
+            skipped: true,
+        }
+    }
+}
+
+struct OutputFile {
+    path: String,
+    /// hive style partition values in the path
+    partition: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SinkFormat {
+    Parquet,
+    Csv,
+    Json,
+    Arrow,
+}
+
+impl SinkFormat {
+    fn parse(format: &str) -> eyre::Result<Self> {
+        match format.to_lowercase().as_str() {
+            // `s3` is the parquet format of older configs
+            "parquet" | "s3" => Ok(SinkFormat::Parquet),
+            "csv" => Ok(SinkFormat::Csv),
+            "json" | "ndjson" => Ok(SinkFormat::Json),
+            "arrow" | "ipc" => Ok(SinkFormat::Arrow),
+            v => Err(Error::msg(format!(
+                "unknown sink format {v}, expect parquet, csv, json or arrow"
+            ))),
+        }
+    }
+}
+
+fn csv_writer(sink: &Sink) -> eyre::Result<csv::WriterBuilder> {
+    let options = sink.csv.clone().unwrap_or_default();
+    let mut builder = csv::WriterBuilder::new().with_header(options.header.unwrap_or(true));
+    if let Some(c) = options.delimiter {
+        builder = builder.with_delimiter(byte(c, "delimiter")?);
+    }
+    Ok(builder)
+}
+
+/// an arrow ipc file, object stores get it from a local temporary file
+fn write_arrow_file(
+    ctx: &SessionContext,
+    df: DataFrame,
+    path: &str,
+    sink: &Sink,
+    props: WriterProperties,
+) -> eyre::Result<u64> {
+    let url = ListingTableUrl::parse(path)?;
+    if url.scheme() == "file" {
+        let out: Box<dyn Write + Send> = Box::new(File::create(path)?);
+        return write_stream(df, SinkFormat::Arrow, sink, props, out, false);
+    }
+
+    let tmp = std::env::temp_dir().join(format!("pp-df-{}.arrow", uuid::Uuid::new_v4()));
+    let out: Box<dyn Write + Send> = Box::new(File::create(&tmp)?);
+    let result = write_stream(df, SinkFormat::Arrow, sink, props, out, false).and_then(|rows| {
+        let store = ctx.runtime_env().object_store(url.object_store())?;
+        task::block_on(upload(store.as_ref(), &tmp, url.prefix()))?;
+        Ok(rows)
+    });
+    fs::remove_file(&tmp)?;
+    result
+}
+
+/// stream the local file `from` to `to` in parts, the upload is aborted on errors
+async fn upload(store: &dyn ObjectStore, from: &Path, to: &ObjectPath) -> eyre::Result<()> {
+    let (id, mut writer) = store.put_multipart(to).await?;
+    let result = async {
+        let mut file = File::open(from)?;
+        let mut buf = vec![0; 8 << 20];
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            writer.write_all(&buf[..n]).await?;
+        }
+        writer.shutdown().await
+    }
+    .await;
+
+    if let Err(e) = result {
+        if let Err(abort) = store.abort_multipart(to, &id).await {
+            warn!("can not abort the upload of {to}: {abort}");
+        }
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// write the batches of `df` one by one, e.g. to stdout.
+/// Arrow goes as an ipc stream when `stream` is set, else as an ipc file
+fn write_stream(
+    df: DataFrame,
+    format: SinkFormat,
+    sink: &Sink,
+    props: WriterProperties,
+    out: Box<dyn Write + Send>,
+    stream: bool,
+) -> eyre::Result<u64> {
+    let mut batches = task::block_on(df.execute_stream())?;
+    let schema = batches.schema();
+    let mut writer = match format {
+        SinkFormat::Parquet => {
+            BatchWriter::Parquet(ArrowWriter::try_new(out, schema, Some(props))?)
+        }
+        SinkFormat::Csv => BatchWriter::Csv(csv_writer(sink)?.build(out)),
+        SinkFormat::Json => {
+            if sink
+                .json
+                .as_ref()
+                .and_then(|j| j.compression.as_ref())
+                .is_some()
+            {
+                return Err(Error::msg("json compression is not supported on stdout"));
+            }
+            BatchWriter::Json(LineDelimitedWriter::new(out))
+        }
+        SinkFormat::Arrow if stream => {
+            BatchWriter::ArrowStream(StreamWriter::try_new(out, &schema)?)
+        }
+        SinkFormat::Arrow => BatchWriter::Arrow(FileWriter::try_new(out, &schema)?),
+    };
+
+    let mut rows = 0;
+    while let Some(batch) = task::block_on(batches.next()) {
+        let batch = batch?;
+        rows += batch.num_rows() as u64;
+        match &mut writer {
+            BatchWriter::Parquet(w) => w.write(&batch)?,
+            BatchWriter::Csv(w) => w.write(&batch)?,
+            BatchWriter::Json(w) => w.write(&batch)?,
+            BatchWriter::Arrow(w) => w.write(&batch)?,
+            BatchWriter::ArrowStream(w) => w.write(&batch)?,
+        }
+    }
+    match writer {
+        BatchWriter::Parquet(w) => {
+            w.close()?;
+        }
+        BatchWriter::Csv(w) => {
+            w.into_inner().flush()?;
+        }
+        BatchWriter::Json(mut w) => w.finish()?,
+        BatchWriter::Arrow(mut w) => w.finish()?,
+        BatchWriter::ArrowStream(mut w) => w.finish()?,
+    }
+    Ok(rows)
+}
+
+enum BatchWriter {
+    Parquet(ArrowWriter<Box<dyn Write + Send>>),
+    Csv(csv::Writer<Box<dyn Write + Send>>),
+    Json(LineDelimitedWriter<Box<dyn Write + Send>>),
+    Arrow(FileWriter<Box<dyn Write + Send>>),
+    ArrowStream(StreamWriter<Box<dyn Write + Send>>),
+}
+
+/// rows of the count batches returned by datafusion writes
+fn written_rows(batches: &[RecordBatch]) -> u64 {
+    batches
+        .iter()
+        .filter_map(|b| b.column(0).as_any().downcast_ref::<UInt64Array>())
+        .flat_map(|a| a.iter().flatten())
+        .sum()
+}
 
+struct JobReport {
+    name: String,
+    target: String,
+    seconds: f64,
+    result: eyre::Result<JobOutput>,
+}
+
+/// the jobs with their files as json, for `--report`
+fn write_report(path: &str, reports: &[JobReport]) -> eyre::Result<()> {
+    let jobs = reports
+        .iter()
+        .map(|r| {
+            let mut job = json!({
+                "job": r.name,
+                "target": r.target,
+                "seconds": r.seconds,
+            });
+            match &r.result {
+                Ok(output) => {
+                    let files = output
+                        .files
+                        .iter()
+                        .map(|f| {
+                            json!({
+                                "path": f.path,
+                                "partition": f.partition.iter().cloned().collect::<HashMap<_, _>>(),
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    job["status"] = json!(if output.skipped { "skipped" } else { "ok" });
+                    job["rows"] = json!(output.rows);
+                    job["files"] = json!(files);
+                }
+                Err(e) => {
+                    job["status"] = json!("failed");
+                    job["error"] = json!(e.to_string());
+                }
+            }
+            job
+        })
+        .collect::<Vec<_>>();
+    serde_json::to_writer_pretty(File::create(path)?, &jobs)?;
     Ok(())
 }
 
+fn print_summary(reports: &[JobReport]) {
+    let mut rows = vec![[
+        "job".to_owned(),
+        "rows".to_owned(),
+        "seconds".to_owned(),
+        "target".to_owned(),
+        "status".to_owned(),
+    ]];
+    for r in reports {
+        let (rows_written, status) = match &r.result {
+            Ok(output) if output.skipped => ("-".to_owned(), "skipped".to_owned()),
+            Ok(output) => (output.rows.to_string(), "ok".to_owned()),
+            Err(e) => ("-".to_owned(), format!("failed: {e}")),
+        };
+        rows.push([
+            r.name.clone(),
+            rows_written,
+            format!("{:.1}", r.seconds),
+            r.target.clone(),
+            status,
+        ]);
+    }
+    let mut widths = [0; 5];
+    for row in &rows {
+        for (w, v) in widths.iter_mut().zip(row) {
+            *w = (*w).max(v.len());
+        }
+    }
+    // keep stdout for the data of `-` sinks
+    let to_stderr = reports.iter().any(|r| r.target == "-");
+    for row in &rows {
+        let line = format!(
+            "{:<w0$}  {:>w1$}  {:>w2$}  {:<w3$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            row[4],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+        );
+        match to_stderr {
+            true => eprintln!("{line}"),
+            false => println!("{line}"),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct DFConfig {
+    #[serde(default)]
+    stores: HashMap<String, StoreConfig>,
     source: Vec<Source>,
-    sink: Sink,
+    sink: Option<Sink>,
     query: HashMap<String, String>,
+    /// queries with their own sinks, run over the same sources
+    #[serde(default)]
+    jobs: Vec<Job>,
+    /// named query results read as tables by later stages and jobs
+    #[serde(default)]
+    stages: Vec<Stage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Stage {
+    name: String,
+    /// name of a query in `query`
+    query: String,
+    #[serde(default)]
+    cache: StageCache,
+}
+
+/// how the result of a stage is kept for the queries reading it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum StageCache {
+    /// run again by every query reading it
+    #[default]
+    View,
+    Memory,
+    /// written to temporary parquet files
+    Parquet,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Job {
+    name: String,
+    /// name of a query in `query`
+    query: String,
+    sink: Sink,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -284,90 +1108,1191 @@ struct Source {
     name: String,
     format: String,
     header: Option<bool>,
-    path: String,
-    schema: Option<Vec<HashMap<String, String>>>,
+    path: SourcePath,
+    schema: Option<Vec<ColumnDef>>,
+    store: Option<StoreRef>,
+    /// hive style partition columns of the path, e.g. `- date: date`
+    #[serde(default)]
+    partition_cols: Vec<ColumnDef>,
+    csv: Option<CsvOptions>,
+    parquet: Option<ParquetOptions>,
+    json: Option<JsonOptions>,
+    avro: Option<AvroOptions>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// one path or a list of them, each a file, a directory or a glob
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum SourcePath {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl SourcePath {
+    fn paths(&self) -> Vec<String> {
+        match self {
+            SourcePath::One(path) => vec![path.clone()],
+            SourcePath::Many(paths) => paths.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ParquetOptions {
+    /// default is .parquet
+    file_extension: Option<String>,
+    /// prune row groups by their statistics, default is the session setting
+    pruning: Option<bool>,
+    /// ignore the key value metadata of the files, which may differ between them
+    skip_metadata: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonOptions {
+    /// default is .json plus the extension of the compression
+    file_extension: Option<String>,
+    /// gzip, bzip2, xz, zstd or uncompressed
+    compression: Option<String>,
+    schema_infer_max_records: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AvroOptions {
+    /// default is .avro
+    file_extension: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CsvOptions {
+    delimiter: Option<char>,
+    quote: Option<char>,
+    escape: Option<char>,
+    /// not supported yet, the csv reader of datafusion 36 can not skip comment
+    /// lines, so setting it is an error rather than reading them as rows
+    comment: Option<char>,
+    /// default is .csv plus the extension of the compression, e.g. .csv.gz
+    file_extension: Option<String>,
+    /// gzip, bzip2, xz, zstd or uncompressed
+    compression: Option<String>,
+    schema_infer_max_records: Option<usize>,
+    /// values read as null, e.g. \N, in columns of primitive types and strings
+    #[serde(default)]
+    null_values: Vec<String>,
+}
+
+/// a column of a source schema, `name: type` or a map with a nullable flag
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum ColumnDef {
+    Full {
+        name: String,
+        #[serde(rename = "type")]
+        data_type: String,
+        #[serde(default)]
+        nullable: bool,
+        /// chrono format of timestamp and date values in csv files
+        format: Option<String>,
+    },
+    Short(HashMap<String, String>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Sink {
+    /// parquet, csv, json or arrow
     format: String,
+    /// `-` for stdout
     path: String,
-    parameters: HashMap<String, String>,
-    columns: Vec<HashMap<String, String>>,
-    s3: Option<HashMap<String, String>>,
+    #[serde(default)]
+    parameters: WriterParams,
+    #[serde(default)]
+    columns: Vec<ColumnEntry>,
+    // `s3` is the inline store of older configs
+    #[serde(alias = "s3")]
+    store: Option<StoreRef>,
+    csv: Option<CsvSinkOptions>,
+    json: Option<JsonSinkOptions>,
+    /// hive style partition columns, the path is a directory then
+    #[serde(default)]
+    partition_by: Vec<String>,
+    /// rows per file, roughly, the path is a directory then
+    max_rows_per_file: Option<usize>,
+    /// prefix of the file names in a directory
+    file_prefix: Option<String>,
+    /// what to do when the path exists
+    #[serde(default)]
+    mode: SinkMode,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SinkMode {
+    #[default]
+    Error,
+    /// replace the file or the files of the directory, old files are removed
+    /// after the new ones are in place
+    Overwrite,
+    /// add files to the directory, failing instead of replacing one
+    Append,
+    /// skip the job
+    Ignore,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CsvSinkOptions {
+    /// default is true
+    header: Option<bool>,
+    delimiter: Option<char>,
 }
 
-fn get_encoding(parameters: &HashMap<String, String>) -> Encoding {
-    let encoding = match parameters.get("encoding") {
-        Some(v) => String::from(v).to_uppercase(),
-        None => String::from("PLAIN"),
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonSinkOptions {
+    /// gzip, bzip2, xz, zstd or uncompressed
+    compression: Option<String>,
+}
+
+/// parquet writer settings of a sink, the column ones are defaults for all columns
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct WriterParams {
+    max_group_size: Option<usize>,
+    /// 1 or 2, default is 2
+    writer_version: Option<u8>,
+    /// rows per write of a column, default is 16M
+    write_batch_size: Option<usize>,
+    /// bytes of a data page, roughly
+    data_page_size: Option<usize>,
+    /// rows of a data page, roughly
+    data_page_row_count: Option<usize>,
+    /// bytes of a dictionary page, a column falls back to plain beyond it
+    dictionary_page_size: Option<usize>,
+    /// bytes of the min and max values in the column index
+    column_index_truncate_length: Option<usize>,
+    /// default is pp
+    created_by: Option<String>,
+    #[serde(default)]
+    key_value_metadata: BTreeMap<String, String>,
+    /// the order of the rows in the files, the query has to sort them so
+    #[serde(default)]
+    sorting_columns: Vec<SortColumn>,
+    #[serde(flatten)]
+    column: ColumnParams,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, serde_yaml::Value>,
+}
+
+/// parquet writer settings of a column
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ColumnParams {
+    /// like snappy, zstd or ZSTD(3)
+    #[serde(
+        default,
+        deserialize_with = "de_compression",
+        serialize_with = "ser_compression"
+    )]
+    compression: Option<Compression>,
+    /// like plain or delta_binary_packed, dictionaries are set with `dictionary`
+    #[serde(
+        default,
+        deserialize_with = "de_encoding",
+        serialize_with = "ser_encoding"
+    )]
+    encoding: Option<Encoding>,
+    /// default is false
+    dictionary: Option<bool>,
+    /// true, false, none, chunk or page, true is chunk
+    statistic: Option<Statistic>,
+    /// bytes of the min and max statistics, default is 1024
+    max_statistics_size: Option<usize>,
+    /// default is false, setting the fpp or ndv turns it on
+    bloom_filter: Option<bool>,
+    /// false positive probability of the bloom filters, default is 0.05
+    #[serde(default, deserialize_with = "de_fpp")]
+    bloom_filter_fpp: Option<f64>,
+    /// distinct values a bloom filter is sized for, default is 1M
+    bloom_filter_ndv: Option<u64>,
+}
+
+/// a column the rows are sorted by, see [`sorting_columns`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SortColumn {
+    name: String,
+    #[serde(default)]
+    descending: bool,
+    #[serde(default)]
+    nulls_first: bool,
+}
+
+fn de_compression<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Compression>, D::Error> {
+    Option::<String>::deserialize(d)?
+        .map(|v| parse_compression(&v).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// the inverse of [`parse_compression`], compressions display their level as `ZstdLevel(3)`
+fn ser_compression<S: Serializer>(v: &Option<Compression>, s: S) -> Result<S::Ok, S::Error> {
+    match v {
+        Some(Compression::GZIP(l)) => s.serialize_str(&format!("GZIP({})", l.compression_level())),
+        Some(Compression::BROTLI(l)) => {
+            s.serialize_str(&format!("BROTLI({})", l.compression_level()))
+        }
+        Some(Compression::ZSTD(l)) => s.serialize_str(&format!("ZSTD({})", l.compression_level())),
+        Some(v) => s.collect_str(v),
+        None => s.serialize_none(),
+    }
+}
+
+fn de_encoding<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Encoding>, D::Error> {
+    Option::<String>::deserialize(d)?
+        .map(|v| parse_encoding(&v).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+fn ser_encoding<S: Serializer>(v: &Option<Encoding>, s: S) -> Result<S::Ok, S::Error> {
+    match v {
+        Some(v) => s.collect_str(v),
+        None => s.serialize_none(),
+    }
+}
+
+fn de_fpp<'de, D: Deserializer<'de>>(d: D) -> Result<Option<f64>, D::Error> {
+    match Option::<f64>::deserialize(d)? {
+        Some(v) if !(v > 0.0 && v < 1.0) => Err(serde::de::Error::custom(format!(
+            "bloom_filter_fpp {v}, expect between 0 and 1"
+        ))),
+        v => Ok(v),
+    }
+}
+
+/// writer settings of the columns matching `name`, see [`column_settings`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ColumnEntry {
+    name: String,
+    #[serde(flatten)]
+    params: ColumnParams,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, serde_yaml::Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Statistic {
+    Enabled(bool),
+    Level(StatisticLevel),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum StatisticLevel {
+    None,
+    Chunk,
+    Page,
+}
+
+impl Statistic {
+    fn enabled(self) -> EnabledStatistics {
+        match self {
+            Statistic::Enabled(false) | Statistic::Level(StatisticLevel::None) => {
+                EnabledStatistics::None
+            }
+            Statistic::Enabled(true) | Statistic::Level(StatisticLevel::Chunk) => {
+                EnabledStatistics::Chunk
+            }
+            Statistic::Level(StatisticLevel::Page) => EnabledStatistics::Page,
+        }
+    }
+}
+
+impl WriterParams {
+    fn apply(&self, props: WriterPropertiesBuilder) -> eyre::Result<WriterPropertiesBuilder> {
+        if let Some(key) = self.unknown.keys().next() {
+            return Err(Error::msg(format!("unknown sink parameter {key}")));
+        }
+        let version = match self.writer_version.unwrap_or(2) {
+            1 => WriterVersion::PARQUET_1_0,
+            2 => WriterVersion::PARQUET_2_0,
+            v => return Err(Error::msg(format!("writer_version {v}, expect 1 or 2"))),
+        };
+        let mut props = props
+            // file settings
+            .set_writer_version(version)
+            .set_created_by(self.created_by.clone().unwrap_or_else(|| "pp".to_owned()))
+            .set_write_batch_size(self.write_batch_size.unwrap_or(16 * 1024 * 1024))
+            .set_dictionary_enabled(false)
+            .set_max_statistics_size(1024);
+
+        if let Some(v) = self.max_group_size {
+            props = props.set_max_row_group_size(v);
+        }
+        if let Some(v) = self.data_page_size {
+            props = props.set_data_page_size_limit(v);
+        }
+        if let Some(v) = self.data_page_row_count {
+            props = props.set_data_page_row_count_limit(v);
+        }
+        if let Some(v) = self.dictionary_page_size {
+            props = props.set_dictionary_page_size_limit(v);
+        }
+        if let Some(v) = self.column_index_truncate_length {
+            props = props.set_column_index_truncate_length(Some(v));
+        }
+        if !self.key_value_metadata.is_empty() {
+            let metadata = self
+                .key_value_metadata
+                .iter()
+                .map(|(k, v)| KeyValue::new(k.clone(), v.clone()))
+                .collect();
+            props = props.set_key_value_metadata(Some(metadata));
+        }
+
+        let column = &self.column;
+        if let Some(v) = column.compression {
+            props = props.set_compression(v);
+        }
+        if let Some(v) = column.encoding {
+            props = props.set_encoding(v);
+        }
+        if let Some(v) = column.dictionary {
+            props = props.set_dictionary_enabled(v);
+        }
+        if let Some(v) = column.statistic {
+            props = props.set_statistics_enabled(v.enabled());
+        }
+        if let Some(v) = column.max_statistics_size {
+            props = props.set_max_statistics_size(v);
+        }
+        // the fpp and ndv turn the bloom filter on, `bloom_filter: false` wins
+        if let Some(v) = column.bloom_filter_fpp {
+            props = props.set_bloom_filter_fpp(v);
+        }
+        if let Some(v) = column.bloom_filter_ndv {
+            props = props.set_bloom_filter_ndv(v);
+        }
+        if let Some(v) = column.bloom_filter {
+            props = props.set_bloom_filter_enabled(v);
+        }
+        Ok(props)
+    }
+}
+
+impl ColumnParams {
+    fn apply_column(
+        &self,
+        props: WriterPropertiesBuilder,
+        col: ColumnPath,
+    ) -> eyre::Result<WriterPropertiesBuilder> {
+        let mut props = props;
+        if let Some(v) = self.compression {
+            props = props.set_column_compression(col.clone(), v);
+        }
+        if let Some(v) = self.encoding {
+            props = props.set_column_encoding(col.clone(), v);
+        }
+        if let Some(v) = self.dictionary {
+            props = props.set_column_dictionary_enabled(col.clone(), v);
+        }
+        if let Some(v) = self.statistic {
+            props = props.set_column_statistics_enabled(col.clone(), v.enabled());
+        }
+        if let Some(v) = self.max_statistics_size {
+            props = props.set_column_max_statistics_size(col.clone(), v);
+        }
+        if let Some(v) = self.bloom_filter_fpp {
+            props = props.set_column_bloom_filter_fpp(col.clone(), v);
+        }
+        if let Some(v) = self.bloom_filter_ndv {
+            props = props.set_column_bloom_filter_ndv(col.clone(), v);
+        }
+        if let Some(v) = self.bloom_filter {
+            props = props.set_column_bloom_filter_enabled(col, v);
+        }
+        Ok(props)
+    }
+
+    /// take the settings unset here from `other`
+    fn merge(&mut self, other: &ColumnParams) {
+        self.compression = self.compression.or(other.compression);
+        self.encoding = self.encoding.or(other.encoding);
+        self.dictionary = self.dictionary.or(other.dictionary);
+        self.statistic = self.statistic.or(other.statistic);
+        self.max_statistics_size = self.max_statistics_size.or(other.max_statistics_size);
+        self.bloom_filter = self.bloom_filter.or(other.bloom_filter);
+        self.bloom_filter_fpp = self.bloom_filter_fpp.or(other.bloom_filter_fpp);
+        self.bloom_filter_ndv = self.bloom_filter_ndv.or(other.bloom_filter_ndv);
+    }
+
+    /// the bloom filter is on, the fpp and ndv turn it on
+    fn bloom_filter_enabled(&self) -> bool {
+        self.bloom_filter
+            .unwrap_or(self.bloom_filter_fpp.is_some() || self.bloom_filter_ndv.is_some())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.compression.is_none()
+            && self.encoding.is_none()
+            && self.dictionary.is_none()
+            && self.statistic.is_none()
+            && self.max_statistics_size.is_none()
+            && self.bloom_filter.is_none()
+            && self.bloom_filter_fpp.is_none()
+            && self.bloom_filter_ndv.is_none()
+    }
+}
+
+/// fields of the column definitions, with the patterns of their names
+fn column_defs(defs: &[ColumnDef]) -> eyre::Result<(Vec<Field>, Vec<ColumnPattern>)> {
+    let mut fields = vec![];
+    let mut patterns = vec![];
+    for def in defs {
+        let field = build_fields(def)?;
+        let pattern = ColumnPattern::parse(field.name()).map_err(Error::msg)?;
+        fields.push(field);
+        patterns.push(pattern);
+    }
+    Ok((fields, patterns))
+}
+
+fn byte(c: char, option: &str) -> eyre::Result<u8> {
+    u8::try_from(c).map_err(|_| Error::msg(format!("csv {option} {c:?} must be one byte")))
+}
+
+/// register a csv source. Null values and formatted timestamps are read as
+/// text into a `<name>__raw` table, the source is a view converting them
+fn register_csv(ctx: &SessionContext, src: &Source, paths: &[String]) -> eyre::Result<()> {
+    let csv = src.csv.clone().unwrap_or_default();
+    let mut opt = CsvReadOptions::default();
+
+    opt.has_header = src.header.unwrap_or_else(|| false);
+    if let Some(c) = csv.delimiter {
+        opt.delimiter = byte(c, "delimiter")?;
+    }
+    if let Some(c) = csv.quote {
+        opt.quote = byte(c, "quote")?;
+    }
+    if let Some(c) = csv.escape {
+        opt.escape = Some(byte(c, "escape")?);
+    }
+    if let Some(c) = csv.comment {
+        return Err(Error::msg(format!(
+            "source {}: csv comment {c:?} is not supported, the csv reader of \
+             this datafusion version can not skip comment lines",
+            src.name
+        )));
+    }
+    if let Some(v) = csv.schema_infer_max_records {
+        opt.schema_infer_max_records = v;
+    }
+    if let Some(v) = &csv.compression {
+        opt.file_compression_type = FileCompressionType::from_str(v)
+            .map_err(|e| Error::msg(format!("csv compression {v}: {e}")))?;
+    }
+    let file_extension = match &csv.file_extension {
+        Some(v) => v.clone(),
+        None => format!(".csv{}", opt.file_compression_type.get_ext()),
     };
-    Encoding::from_str(encoding.as_str()).unwrap_or_else(|_| {
-        warn!("unknown encoding type: {}, use PLAIN by default", encoding);
-        Encoding::PLAIN
-    })
+    opt.file_extension = &file_extension;
+    opt.table_partition_cols = partition_cols(src)?;
+    let listing = Listing::new(ctx, paths, &opt)?;
+
+    let defs = src.schema.clone().unwrap_or_default();
+    let schema = source_schema(ctx, src, &listing)?;
+
+    let (_, patterns) = column_defs(&defs)?;
+    let formats = schema
+        .fields()
+        .iter()
+        .filter_map(|f| {
+            let def = &defs[*matching(&patterns, f.name()).first()?];
+            match def {
+                ColumnDef::Full {
+                    format: Some(format),
+                    ..
+                } => Some((f.name().clone(), format.clone())),
+                _ => None,
+            }
+        })
+        .collect::<HashMap<_, _>>();
+
+    if csv.null_values.is_empty() && formats.is_empty() {
+        return listing.register(ctx, &src.name, schema);
+    }
+
+    // columns read as text and cast to their type afterwards, the ones with a
+    // format and with null values all primitive and string ones
+    let text = |f: &Field| {
+        formats.contains_key(f.name())
+            || (!csv.null_values.is_empty()
+                && (f.data_type().is_primitive()
+                    || matches!(
+                        f.data_type(),
+                        DataType::Boolean
+                            | DataType::Utf8
+                            | DataType::LargeUtf8
+                            | DataType::Binary
+                            | DataType::LargeBinary
+                    )))
+    };
+    let raw_fields = schema
+        .fields()
+        .iter()
+        .map(|f| match text(f) {
+            true => Field::new(f.name(), DataType::Utf8, true),
+            false => f.as_ref().clone(),
+        })
+        .collect::<Vec<_>>();
+    let raw_name = format!("{}__raw", src.name);
+    listing.register(ctx, &raw_name, Schema::new(raw_fields))?;
+
+    let quote_str = |v: &str| format!("'{}'", v.replace('\'', "''"));
+    let mut columns = schema
+        .fields()
+        .iter()
+        .map(|f| {
+            let name = quote_ident(f.name());
+            if !text(f) {
+                return Ok(name);
+            }
+            let mut expr = name.clone();
+            if !csv.null_values.is_empty() {
+                let nulls = csv
+                    .null_values
+                    .iter()
+                    .map(|v| quote_str(v))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                expr = format!("CASE WHEN {expr} IN ({nulls}) THEN NULL ELSE {expr} END");
+            }
+            if let Some(format) = formats.get(f.name()) {
+                expr = match f.data_type() {
+                    DataType::Timestamp(_, _) | DataType::Date32 | DataType::Date64 => {
+                        format!("to_timestamp({expr}, {})", quote_str(format))
+                    }
+                    t => {
+                        return Err(Error::msg(format!(
+                            "column {} has a format but is {t}, not a timestamp or date",
+                            f.name()
+                        )))
+                    }
+                };
+            }
+            Ok(format!("{expr} AS {name}"))
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+    for (name, _) in &opt.table_partition_cols {
+        columns.push(quote_ident(name));
+    }
+
+    let sql = format!(
+        "SELECT {} FROM {}",
+        columns.join(", "),
+        quote_ident(&raw_name)
+    );
+    debug!("{sql}");
+    let df = task::block_on(ctx.sql(&sql))?;
+
+    // cast the text columns back with expressions, sql casts only know sql types
+    let mut exprs = schema
+        .fields()
+        .iter()
+        .map(|f| {
+            let column = Expr::Column(Column::from_name(f.name()));
+            match text(f) {
+                true => cast(column, f.data_type().clone()).alias(f.name()),
+                false => column,
+            }
+        })
+        .collect::<Vec<_>>();
+    for (name, _) in &opt.table_partition_cols {
+        exprs.push(Expr::Column(Column::from_name(name)));
+    }
+    let view = df.select(exprs)?.into_view();
+    ctx.register_table(src.name.as_str(), view)?;
+    Ok(())
 }
 
-fn get_compression(parameters: &HashMap<String, String>) -> Compression {
-    let compression = match parameters.get("compression") {
-        Some(v) => String::from(v).to_uppercase(),
-        None => String::from("ZSTD"),
+fn partition_cols(src: &Source) -> eyre::Result<Vec<(String, DataType)>> {
+    src.partition_cols
+        .iter()
+        .map(|def| build_fields(def).map(|f| (f.name().clone(), f.data_type().clone())))
+        .collect()
+}
+
+/// schema of the files of a source, inferred unless every column is defined
+fn source_schema(ctx: &SessionContext, src: &Source, listing: &Listing) -> eyre::Result<Schema> {
+    match src.schema.as_deref() {
+        None | Some([]) => listing.infer_schema(ctx),
+        Some(defs) => {
+            let formats = defs.iter().any(|d| {
+                matches!(
+                    d,
+                    ColumnDef::Full {
+                        format: Some(_),
+                        ..
+                    }
+                )
+            });
+            if formats && src.format != "csv" {
+                return Err(Error::msg(format!(
+                    "column formats are only supported by csv sources, not {}",
+                    src.name
+                )));
+            }
+            build_schema(&src.name, defs, || listing.infer_schema(ctx))
+        }
+    }
+}
+
+fn register_parquet(ctx: &SessionContext, src: &Source, paths: &[String]) -> eyre::Result<()> {
+    let parquet = src.parquet.clone().unwrap_or_default();
+    let mut opt = ParquetReadOptions::default();
+    if let Some(v) = &parquet.file_extension {
+        opt.file_extension = v;
+    }
+    opt.parquet_pruning = parquet.pruning;
+    opt.skip_metadata = parquet.skip_metadata;
+    opt.table_partition_cols = partition_cols(src)?;
+
+    info!("register parquet {}", src.name);
+    let listing = Listing::new(ctx, paths, &opt)?;
+    listing.register(ctx, &src.name, source_schema(ctx, src, &listing)?)
+}
+
+fn register_json(ctx: &SessionContext, src: &Source, paths: &[String]) -> eyre::Result<()> {
+    let json = src.json.clone().unwrap_or_default();
+    let mut opt = NdJsonReadOptions::default();
+    if let Some(v) = &json.compression {
+        opt.file_compression_type = FileCompressionType::from_str(v)
+            .map_err(|e| Error::msg(format!("json compression {v}: {e}")))?;
+    }
+    let file_extension = match &json.file_extension {
+        Some(v) => v.clone(),
+        None => format!(".json{}", opt.file_compression_type.get_ext()),
     };
+    opt.file_extension = &file_extension;
+    if let Some(v) = json.schema_infer_max_records {
+        opt.schema_infer_max_records = v;
+    }
+    opt.table_partition_cols = partition_cols(src)?;
 
-    Compression::from_str(compression.as_str()).unwrap_or_else(|_| {
-        warn!(
-            "unknown compression type: {}, use ZSTD by default",
-            compression
-        );
-        Compression::ZSTD(ZstdLevel::default())
-    })
+    let listing = Listing::new(ctx, paths, &opt)?;
+    listing.register(ctx, &src.name, source_schema(ctx, src, &listing)?)
 }
 
-fn build_fields(col: &HashMap<String, String>) -> Field {
-    let (name, datatype) = col.into_iter().next().unwrap();
-    let arrow_type = match datatype.as_str() {
-        "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
-        "decimal" => DataType::Decimal128(20, 10),
-        _ => DataType::Utf8,
+fn register_avro(ctx: &SessionContext, src: &Source, paths: &[String]) -> eyre::Result<()> {
+    let avro = src.avro.clone().unwrap_or_default();
+    let mut opt = AvroReadOptions::default();
+    if let Some(v) = &avro.file_extension {
+        opt.file_extension = v;
+    }
+    opt.table_partition_cols = partition_cols(src)?;
+
+    let listing = Listing::new(ctx, paths, &opt)?;
+    listing.register(ctx, &src.name, source_schema(ctx, src, &listing)?)
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// schema of a source from its column definitions. Names may be patterns,
+/// see [`ColumnPattern`], then the columns come from the inferred schema and
+/// take the type of the most specific definition matching them
+fn build_schema(
+    source: &str,
+    defs: &[ColumnDef],
+    infer: impl FnOnce() -> eyre::Result<Schema>,
+) -> eyre::Result<Schema> {
+    let (fields, patterns) = column_defs(defs)?;
+
+    if patterns.iter().all(|p| p.names().is_some()) {
+        // exact names and ranges list the columns in file order
+        let mut sbuilder = SchemaBuilder::new();
+        for (field, pattern) in fields.iter().zip(&patterns) {
+            for name in pattern.names().unwrap() {
+                sbuilder.push(field.clone().with_name(name));
+            }
+        }
+        return Ok(sbuilder.finish());
+    }
+
+    let inferred = infer()?;
+    let mut sbuilder = SchemaBuilder::new();
+    for f in inferred.fields() {
+        let name = f.name();
+        let field = match matching(&patterns, name).first() {
+            Some(&i) => fields[i].clone().with_name(name),
+            None => Field::new(name, f.data_type().clone(), f.is_nullable()),
+        };
+        sbuilder.push(field);
+    }
+    for pattern in &patterns {
+        for name in pattern.names().unwrap_or_default() {
+            if inferred.field_with_name(name).is_err() {
+                warn!("column {name} of the schema is not in source {source}");
+            }
+        }
+    }
+    Ok(sbuilder.finish())
+}
+
+/// parquet writer properties of `sink` for a result of `columns`
+fn writer_properties(sink: &Sink, columns: &[String]) -> eyre::Result<WriterProperties> {
+    let mut props = sink.parameters.apply(WriterProperties::builder())?;
+    let settings = column_settings(&sink.columns, columns)?;
+    let sink_params = &sink.parameters.column;
+
+    // parquet can not turn a sink bloom filter off for a column, the sink
+    // filter goes to each column then
+    let per_column = sink_params.bloom_filter_enabled()
+        && settings.iter().any(|(_, p)| p.bloom_filter == Some(false));
+    if per_column {
+        props = props.set_bloom_filter_enabled(false);
+    }
+    for name in columns {
+        let params = settings.iter().find(|(n, _)| n == name).map(|(_, p)| p);
+        let mut params = match (params, per_column) {
+            (Some(p), _) => p.clone(),
+            (None, true) => ColumnParams::default(),
+            (None, false) => continue,
+        };
+        // a column bloom filter does not fall back to the sink fpp and ndv
+        params.merge(sink_params);
+        props = params.apply_column(props, ColumnPath::from(name.as_str()))?;
+    }
+
+    // partition columns are not in the files
+    let file_columns = columns
+        .iter()
+        .filter(|c| !sink.partition_by.contains(c))
+        .cloned()
+        .collect::<Vec<_>>();
+    let sorting = sorting_columns(&sink.parameters.sorting_columns, &file_columns)?;
+    if !sorting.is_empty() {
+        props = props.set_sorting_columns(Some(sorting));
+    }
+    Ok(props.build())
+}
+
+/// the row group sorting metadata of `columns`, indices are into `names`
+fn sorting_columns(columns: &[SortColumn], names: &[String]) -> eyre::Result<Vec<SortingColumn>> {
+    columns
+        .iter()
+        .map(|c| {
+            let idx = names.iter().position(|n| *n == c.name).ok_or_else(|| {
+                Error::msg(format!(
+                    "sorting column {} is not in the written columns",
+                    c.name
+                ))
+            })?;
+            Ok(SortingColumn::new(idx as i32, c.descending, c.nulls_first))
+        })
+        .collect()
+}
+
+/// writer settings of each output column. Entries are matched by name pattern,
+/// a setting comes from the most specific entry having it, see [`matching`]
+fn column_settings(
+    columns: &[ColumnEntry],
+    names: &[String],
+) -> eyre::Result<Vec<(String, ColumnParams)>> {
+    let mut patterns = vec![];
+    for entry in columns {
+        if let Some(key) = entry.unknown.keys().next() {
+            return Err(Error::msg(format!(
+                "sink column {}: {key} is not a column setting",
+                entry.name
+            )));
+        }
+        patterns.push(ColumnPattern::parse(&entry.name).map_err(Error::msg)?);
+    }
+
+    for pattern in &patterns {
+        for name in pattern.names().unwrap_or_default() {
+            if !names.contains(name) {
+                warn!("sink column {name} is not in the query result");
+            }
+        }
+    }
+
+    let mut result = vec![];
+    for name in names {
+        let mut settings = ColumnParams::default();
+        for i in matching(&patterns, name) {
+            settings.merge(&columns[i].params);
+        }
+        if !settings.is_empty() {
+            result.push((name.clone(), settings));
+        }
+    }
+    Ok(result)
+}
+
+fn build_fields(col: &ColumnDef) -> eyre::Result<Field> {
+    let (name, datatype, nullable) = match col {
+        ColumnDef::Full {
+            name,
+            data_type,
+            nullable,
+            ..
+        } => (name, data_type, *nullable),
+        ColumnDef::Short(col) => {
+            if col.len() != 1 {
+                return Err(Error::msg(format!(
+                    "schema entry {col:?} must have exactly one column"
+                )));
+            }
+            let (name, datatype) = col.iter().next().unwrap();
+            (name, datatype, false)
+        }
     };
-    Field::new(name, arrow_type, false)
+    let arrow_type = parse_type(datatype).map_err(|e| Error::msg(format!("column {name}: {e}")))?;
+    Ok(Field::new(name, arrow_type, nullable))
 }
 
 #[cfg(test)]
 mod tests {
+    use datafusion::arrow::array::Int64Array;
+    use datafusion::parquet::basic::ZstdLevel;
+
     use super::*;
+    use crate::cmd::utils::TempDir;
 
     #[test]
     fn test_get_compression() {
-        let mut parameters = HashMap::new();
-        parameters.insert("compression".to_owned(), "zstd".to_owned());
         assert_eq!(
-            get_compression(&parameters),
-            Compression::ZSTD(ZstdLevel::default())
+            parse_compression::<Compression>("zstd"),
+            Ok(Compression::ZSTD(ZstdLevel::default()))
         );
     }
 
     #[test]
     fn test_get_compression_bad_value() {
-        let mut parameters = HashMap::new();
-        parameters.insert("compression".to_owned(), "bad".to_owned());
-        assert_eq!(
-            get_compression(&parameters),
-            Compression::ZSTD(ZstdLevel::default())
-        )
+        // unknown compressions fail instead of falling back to zstd
+        assert!(parse_compression::<Compression>("bad").is_err());
     }
 
     #[test]
     fn test_get_encoding() {
-        let mut parameters = HashMap::new();
-        parameters.insert("encoding".to_owned(), "PLAIN".to_owned());
-        assert_eq!(get_encoding(&parameters), Encoding::PLAIN);
+        assert_eq!(parse_encoding::<Encoding>("PLAIN"), Ok(Encoding::PLAIN));
     }
 
     #[test]
     fn test_get_encoding_bad_value() {
-        let mut parameters = HashMap::new();
-        parameters.insert("encoding".to_owned(), "bad".to_owned());
-        assert_eq!(get_encoding(&parameters), Encoding::PLAIN)
+        // unknown encodings fail instead of falling back to plain
+        assert!(parse_encoding::<Encoding>("bad").is_err());
+        assert!(parse_encoding::<Encoding>("RLE_DICTIONARY").is_err());
+    }
+
+    const JOBS: &str = "
+source: []
+query:
+  daily: select 1
+  hourly: select 2
+jobs:
+  - name: daily
+    query: daily
+    sink:
+      format: parquet
+      path: daily.parquet
+  - name: hourly
+    query: hourly
+    sink:
+      format: parquet
+      path: hourly.parquet
+";
+
+    #[test]
+    fn test_select_jobs() {
+        let cfg: DFConfig = serde_yaml::from_str(JOBS).unwrap();
+        let jobs = select_jobs(&cfg, None, None, &[]).unwrap();
+        assert_eq!(jobs.len(), 2);
+
+        let jobs = select_jobs(&cfg, None, None, &["hourly".to_owned()]).unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].sink.path, "hourly.parquet");
+
+        assert!(select_jobs(&cfg, None, None, &["weekly".to_owned()]).is_err());
+        assert!(select_jobs(&cfg, Some("daily".to_owned()), None, &[]).is_err());
+    }
+
+    #[test]
+    fn test_temp_sibling() {
+        let tmp = temp_sibling("s3://testdata/out/sink.parquet");
+        assert!(tmp.starts_with("s3://testdata/out/.pp-tmp-"));
+        assert!(tmp.ends_with("-sink.parquet"));
+
+        let tmp = temp_sibling("dataset/");
+        assert!(tmp.starts_with(".pp-tmp-"));
+        assert!(tmp.ends_with("-dataset/"));
+    }
+
+    #[test]
+    fn test_writer_params() {
+        let params: WriterParams = serde_yaml::from_str(
+            "max_group_size: 86400\nwriter_version: 1\nstatistic: page\ndictionary: true\n",
+        )
+        .unwrap();
+        assert_eq!(params.max_group_size, Some(86400));
+        assert_eq!(
+            params.column.statistic,
+            Some(Statistic::Level(StatisticLevel::Page))
+        );
+        assert!(params.apply(WriterProperties::builder()).is_ok());
+
+        let params: WriterParams = serde_yaml::from_str("statistics: false").unwrap();
+        assert!(params.apply(WriterProperties::builder()).is_err());
+    }
+
+    #[test]
+    fn test_column_settings() {
+        let columns: Vec<ColumnEntry> = serde_yaml::from_str(
+            "- {name: 'dp_*', compression: snappy, statistic: false}\n- {name: dp_0001, statistic: true}\n",
+        )
+        .unwrap();
+        let names = vec!["dp_0001".to_owned(), "dp_0002".to_owned(), "x".to_owned()];
+        let settings = column_settings(&columns, &names).unwrap();
+        assert_eq!(settings.len(), 2);
+        assert_eq!(settings[0].1.statistic, Some(Statistic::Enabled(true)));
+        assert_eq!(settings[0].1.compression, Some(Compression::SNAPPY));
+        assert_eq!(settings[1].1.statistic, Some(Statistic::Enabled(false)));
+
+        let columns: Vec<ColumnEntry> =
+            serde_yaml::from_str("- {name: x, max_group_size: 10}").unwrap();
+        assert!(column_settings(&columns, &names).is_err());
+    }
+
+    #[test]
+    fn test_writer_properties() {
+        let sink: Sink = serde_yaml::from_str(
+            "format: parquet
+path: x.parquet
+partition_by: [p]
+parameters:
+  compression: ZSTD(3)
+  encoding: delta_binary_packed
+  bloom_filter: true
+  bloom_filter_fpp: 0.01
+  sorting_columns: [{name: b, descending: true}]
+columns:
+  - {name: a, compression: snappy, bloom_filter: false}
+  - {name: b, bloom_filter_ndv: 1000}
+",
+        )
+        .unwrap();
+        let names = ["p", "a", "b", "c"].map(String::from);
+        let props = writer_properties(&sink, &names).unwrap();
+        let (a, b, c) = (
+            ColumnPath::from("a"),
+            ColumnPath::from("b"),
+            ColumnPath::from("c"),
+        );
+
+        assert_eq!(props.compression(&a), Compression::SNAPPY);
+        assert_eq!(
+            props.compression(&b),
+            Compression::ZSTD(ZstdLevel::try_new(3).unwrap())
+        );
+        assert_eq!(props.encoding(&c), Some(Encoding::DELTA_BINARY_PACKED));
+        assert!(props.bloom_filter_properties(&a).is_none());
+        let bloom = props.bloom_filter_properties(&b).unwrap();
+        assert_eq!((bloom.fpp, bloom.ndv), (0.01, 1000));
+        assert_eq!(props.bloom_filter_properties(&c).unwrap().fpp, 0.01);
+        assert_eq!(
+            props.sorting_columns(),
+            Some(&vec![SortingColumn::new(1, true, false)])
+        );
+
+        let mut sink = sink;
+        sink.parameters.sorting_columns[0].name = "p".to_owned();
+        assert!(writer_properties(&sink, &names).is_err());
+    }
+
+    #[test]
+    fn test_bad_writer_params() {
+        for params in [
+            "encoding: rle_dictionary",
+            "encoding: plain_dictionary",
+            "encoding: delta",
+            "compression: lz5",
+            "compression: ZSTD(99)",
+            "bloom_filter_fpp: 1.5",
+        ] {
+            assert!(
+                serde_yaml::from_str::<WriterParams>(params).is_err(),
+                "{params}"
+            );
+        }
+        let columns = "- {name: a, encoding: RLE_DICTIONARY}";
+        assert!(serde_yaml::from_str::<Vec<ColumnEntry>>(columns).is_err());
+    }
+
+    #[test]
+    fn test_json_source() {
+        let dir = TempDir::new(std::env::temp_dir(), "pp-test").unwrap();
+        fs::write(dir.path().join("a.json"), "{\"a\": 1}\n{\"a\": 2}\n").unwrap();
+        // only files with the default extension are read
+        fs::write(dir.path().join("b.txt"), "{\"a\": 4}\n").unwrap();
+        let path = format!("{}/", dir.path().display());
+        let src: Source =
+            serde_yaml::from_str(&format!("{{name: t, format: json, path: '{path}'}}")).unwrap();
+
+        let ctx = SessionContext::new();
+        register_json(&ctx, &src, &[path]).unwrap();
+        let batches =
+            task::block_on(async { ctx.sql("select sum(a) from t").await?.collect().await })
+                .unwrap();
+        let sum = batches[0].column(0).as_any().downcast_ref::<Int64Array>();
+        assert_eq!(sum.map(|a| a.value(0)), Some(3));
+    }
+
+    #[test]
+    fn test_csv_comment() {
+        let src: Source =
+            serde_yaml::from_str("{name: t, format: csv, path: t.csv, csv: {comment: '#'}}")
+                .unwrap();
+        let err = register_csv(&SessionContext::new(), &src, &["t.csv".to_owned()]).unwrap_err();
+        assert!(err.to_string().contains("comment"));
+    }
+
+    fn csv_sink(path: &str, mode: &str) -> Sink {
+        let sink = format!("{{format: csv, path: '{path}', max_rows_per_file: 2, mode: {mode}}}");
+        serde_yaml::from_str(&sink).unwrap()
+    }
+
+    /// names of the files in `dir`, sorted
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_sink_modes() {
+        let dir = TempDir::new(std::env::temp_dir(), "pp-test").unwrap();
+        let out = dir.path().join("out");
+        let target = format!("{}/", out.display());
+        let ctx = SessionContext::new();
+        let mut stores = Stores::new(HashMap::new());
+        let query = "select column1 as a from (values (1), (2), (3))";
+        let mut run = |mode: &str| run_job(&ctx, &mut stores, query, &csv_sink(&target, mode));
+
+        let first = run("error").unwrap();
+        assert_eq!(first.rows, 3);
+        let written = file_names(&out);
+        assert_eq!(written.len(), first.files.len());
+        // nothing is left next to the target
+        assert_eq!(file_names(dir.path()), vec!["out".to_owned()]);
+
+        assert!(run("error").is_err());
+        assert!(run("ignore").unwrap().skipped);
+        assert_eq!(file_names(&out), written);
+
+        let appended = run("append").unwrap();
+        let names = file_names(&out);
+        assert_eq!(names.len(), written.len() + appended.files.len());
+        assert!(written.iter().all(|n| names.contains(n)));
+
+        let overwritten = run("overwrite").unwrap();
+        let names = file_names(&out);
+        assert_eq!(names.len(), overwritten.files.len());
+        assert!(names.iter().all(|n| !written.contains(n)));
+        assert!(!names.contains(&COMMIT_MARKER.to_owned()));
+        assert_eq!(file_names(dir.path()), vec!["out".to_owned()]);
+    }
+
+    #[test]
+    fn test_sink_file_modes() {
+        let dir = TempDir::new(std::env::temp_dir(), "pp-test").unwrap();
+        let path = dir.path().join("out.csv");
+        let target = path.to_string_lossy().to_string();
+        let ctx = SessionContext::new();
+        let mut stores = Stores::new(HashMap::new());
+        let mut run = |query: &str, mode: &str| {
+            let sink = serde_yaml::from_str::<Sink>(&format!(
+                "{{format: csv, path: '{target}', mode: {mode}}}"
+            ))
+            .unwrap();
+            run_job(&ctx, &mut stores, query, &sink)
+        };
+
+        run("select 1 as a", "error").unwrap();
+        assert!(run("select 2 as a", "append").is_err());
+        run("select 3 as a", "overwrite").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\n3\n");
+        assert_eq!(file_names(dir.path()), vec!["out.csv".to_owned()]);
+    }
+
+    #[test]
+    fn test_sink_unfinished_commit() {
+        let dir = TempDir::new(std::env::temp_dir(), "pp-test").unwrap();
+        let out = dir.path().join("out");
+        fs::create_dir(&out).unwrap();
+        fs::write(out.join(COMMIT_MARKER), "{}").unwrap();
+
+        let ctx = SessionContext::new();
+        let mut stores = Stores::new(HashMap::new());
+        let sink = csv_sink(&format!("{}/", out.display()), "overwrite");
+        assert!(run_job(&ctx, &mut stores, "select 1 as a", &sink).is_err());
+    }
+
+    #[test]
+    fn test_sink_partitioned() {
+        let dir = TempDir::new(std::env::temp_dir(), "pp-test").unwrap();
+        let out = dir.path().join("out");
+        let sink: Sink = serde_yaml::from_str(&format!(
+            "{{format: csv, path: '{}', partition_by: [day]}}",
+            out.display()
+        ))
+        .unwrap();
+        let ctx = SessionContext::new();
+        let mut stores = Stores::new(HashMap::new());
+        let query = "select column1 as a, column2 as day from (values (1, 1), (2, 1), (3, 2))";
+
+        let output = run_job(&ctx, &mut stores, query, &sink).unwrap();
+        assert_eq!(output.rows, 3);
+        assert_eq!(
+            file_names(&out),
+            vec!["day=1".to_owned(), "day=2".to_owned()]
+        );
+        let mut partitions = output
+            .files
+            .iter()
+            .map(|f| f.partition.clone())
+            .collect::<Vec<_>>();
+        partitions.dedup();
+        assert_eq!(
+            partitions,
+            vec![
+                vec![("day".to_owned(), "1".to_owned())],
+                vec![("day".to_owned(), "2".to_owned())]
+            ]
+        );
+
+        // the partition column is only in the path
+        let content = |day: &str| {
+            let mut rows = file_names(&out.join(day))
+                .iter()
+                .flat_map(|f| {
+                    let content = fs::read_to_string(out.join(day).join(f)).unwrap();
+                    content.lines().map(str::to_owned).collect::<Vec<_>>()
+                })
+                .filter(|l| l != "a")
+                .collect::<Vec<_>>();
+            rows.sort();
+            rows
+        };
+        assert_eq!(content("day=1"), vec!["1", "2"]);
+        assert_eq!(content("day=2"), vec!["3"]);
     }
 }
@@ -0,0 +1,218 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use eyre::{eyre, Context};
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use parquet::file::metadata::ParquetMetaData;
+
+use crate::cmd::stats::{column_index, row_groups_min_max, StatValue};
+
+#[derive(clap::ValueEnum, Clone, Debug, PartialEq)]
+pub enum InputOrder {
+    /// by path
+    Name,
+    /// by modification time, oldest first
+    Mtime,
+    /// by the min statistic of --order-column
+    Min,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+/// options to expand input directories, globs and file lists
+pub struct InputArgs {
+    #[arg(long)]
+    /// read more input paths from a file, one per line, '-' means stdin
+    files_from: Option<String>,
+
+    #[arg(long, default_value_t = false)]
+    /// only descend into key=value directories when listing directories
+    hive: bool,
+
+    #[arg(long, value_enum, default_value_t = InputOrder::Name)]
+    /// order of the inputs
+    order: InputOrder,
+
+    #[arg(long)]
+    /// column used by --order min
+    order_column: Option<String>,
+}
+
+/// an opened input file with its footer
+pub struct Input {
+    pub path: PathBuf,
+    pub file: File,
+    pub metadata: ParquetMetaData,
+}
+
+impl InputArgs {
+    /// expand `paths` and --files-from into parquet files, opened and ordered
+    pub fn open_inputs(&self, paths: &[String]) -> eyre::Result<Vec<Input>> {
+        let mut entries = paths.to_vec();
+        if let Some(list) = &self.files_from {
+            entries.extend(read_file_list(list)?);
+        }
+
+        let mut seen = HashSet::new();
+        let mut files = vec![];
+        for entry in entries {
+            for path in self.expand(&entry)? {
+                if seen.insert(path.clone()) {
+                    files.push(path);
+                }
+            }
+        }
+
+        let inputs = files
+            .into_iter()
+            .map(|path| {
+                let file = File::open(&path).wrap_err_with(|| format!("open {path:?}"))?;
+                let metadata = parquet::file::footer::parse_metadata(&file)
+                    .wrap_err_with(|| format!("read footer of {path:?}"))?;
+                Ok(Input {
+                    path,
+                    file,
+                    metadata,
+                })
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+
+        let inputs = self.sort(inputs)?;
+        debug!(
+            "inputs: {:?}",
+            inputs.iter().map(|x| &x.path).collect::<Vec<_>>()
+        );
+        Ok(inputs)
+    }
+
+    fn expand(&self, entry: &str) -> eyre::Result<Vec<PathBuf>> {
+        if entry.contains(['*', '?', '[']) {
+            let mut files = vec![];
+            for path in glob::glob(entry)? {
+                let path = path?;
+                if path.is_dir() {
+                    self.walk(&path, &mut files)?;
+                } else {
+                    files.push(path);
+                }
+            }
+            if files.is_empty() {
+                warn!("pattern {entry} matches no file");
+            }
+            return Ok(files);
+        }
+
+        let path = PathBuf::from(entry);
+        if path.is_dir() {
+            let mut files = vec![];
+            self.walk(&path, &mut files)?;
+            return Ok(files);
+        }
+        Ok(vec![path])
+    }
+
+    /// list `.parquet` files below `dir`, skipping hidden and `_` prefixed entries
+    /// like `_SUCCESS` or `.crc` files
+    fn walk(&self, dir: &Path, files: &mut Vec<PathBuf>) -> eyre::Result<()> {
+        let mut entries = std::fs::read_dir(dir)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+
+        for path in entries {
+            let name = path
+                .file_name()
+                .map(|x| x.to_string_lossy().to_string())
+                .unwrap_or_default();
+            if name.starts_with('.') || name.starts_with('_') {
+                continue;
+            }
+            if path.is_dir() {
+                if self.hive && !name.contains('=') {
+                    debug!("skip non partition directory {path:?}");
+                    continue;
+                }
+                self.walk(&path, files)?;
+            } else if path.extension().map_or(false, |e| e == "parquet") {
+                files.push(path);
+            } else {
+                debug!("skip non parquet file {path:?}");
+            }
+        }
+        Ok(())
+    }
+
+    fn sort(&self, inputs: Vec<Input>) -> eyre::Result<Vec<Input>> {
+        let inputs = match self.order {
+            InputOrder::Name => {
+                let mut inputs = inputs;
+                inputs.sort_by(|a, b| a.path.cmp(&b.path));
+                inputs
+            }
+            InputOrder::Mtime => {
+                let keys = inputs
+                    .iter()
+                    .map(|x| x.file.metadata()?.modified())
+                    .collect::<std::io::Result<Vec<SystemTime>>>()?;
+                sort_by_keys(inputs, keys, |a, b| a.cmp(b))
+            }
+            InputOrder::Min => {
+                let column = self
+                    .order_column
+                    .as_ref()
+                    .ok_or_else(|| eyre!("--order min requires --order-column"))?;
+                let keys = inputs
+                    .iter()
+                    .map(|x| {
+                        let schema = x.metadata.file_metadata().schema_descr();
+                        let idx = column_index(schema, column)
+                            .ok_or_else(|| eyre!("column {column} not found in {:?}", x.path))?;
+                        Ok(row_groups_min_max(x.metadata.row_groups(), idx).map(|(min, _)| min))
+                    })
+                    .collect::<eyre::Result<Vec<Option<StatValue>>>>()?;
+                // files without statistics go last
+                sort_by_keys(inputs, keys, |a, b| match (a, b) {
+                    (Some(a), Some(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                })
+            }
+        };
+        Ok(inputs)
+    }
+}
+
+/// sort `inputs` by precomputed `keys`, ties are ordered by path
+fn sort_by_keys<K, F>(inputs: Vec<Input>, keys: Vec<K>, cmp: F) -> Vec<Input>
+where
+    F: Fn(&K, &K) -> Ordering,
+{
+    let mut keyed = keys.into_iter().zip(inputs).collect::<Vec<_>>();
+    keyed.sort_by(|a, b| cmp(&a.0, &b.0).then_with(|| a.1.path.cmp(&b.1.path)));
+    keyed.into_iter().map(|(_, x)| x).collect()
+}
+
+fn read_file_list(list: &str) -> eyre::Result<Vec<String>> {
+    let reader: Box<dyn BufRead> = match list {
+        "-" => Box::new(BufReader::new(std::io::stdin())),
+        path => Box::new(BufReader::new(
+            File::open(path).wrap_err_with(|| format!("open file list {path}"))?,
+        )),
+    };
+
+    let mut paths = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        paths.push(line.to_owned());
+    }
+    Ok(paths)
+}
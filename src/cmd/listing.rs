@@ -0,0 +1,61 @@
+use std::sync::Arc;
+
+use async_std::task;
+use datafusion::arrow::datatypes::Schema;
+use datafusion::datasource::file_format::options::ReadOptions;
+use datafusion::datasource::listing::{
+    ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl,
+};
+use datafusion::prelude::SessionContext;
+use eyre::Error;
+
+/// files of a source, given as paths of files, directories or globs such as
+/// `s3://bucket/events/region=*/`. Directories of object stores end with `/`
+pub struct Listing {
+    urls: Vec<ListingTableUrl>,
+    options: ListingOptions,
+}
+
+impl Listing {
+    pub fn new<'a>(
+        ctx: &SessionContext,
+        paths: &[String],
+        read: &impl ReadOptions<'a>,
+    ) -> eyre::Result<Self> {
+        if paths.is_empty() {
+            return Err(Error::msg("source without path"));
+        }
+        let urls = paths
+            .iter()
+            .map(|p| {
+                ListingTableUrl::parse(p)
+                    .map_err(|e| Error::msg(format!("invalid source path {p}: {e}")))
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+        Ok(Listing {
+            urls,
+            options: read.to_listing_options(&ctx.copied_config()),
+        })
+    }
+
+    /// schema of the files merged over all paths, without the partition columns
+    pub fn infer_schema(&self, ctx: &SessionContext) -> eyre::Result<Schema> {
+        let state = ctx.state();
+        let mut schemas = vec![];
+        for url in &self.urls {
+            let schema = task::block_on(self.options.infer_schema(&state, url))?;
+            schemas.push(schema.as_ref().clone());
+        }
+        Ok(Schema::try_merge(schemas)?)
+    }
+
+    /// register the files as table `name`, `schema` has the columns of the files.
+    /// Partition columns are added after them and are pruned on in filters
+    pub fn register(&self, ctx: &SessionContext, name: &str, schema: Schema) -> eyre::Result<()> {
+        let config = ListingTableConfig::new_with_multi_paths(self.urls.clone())
+            .with_listing_options(self.options.clone())
+            .with_schema(Arc::new(schema));
+        ctx.register_table(name, Arc::new(ListingTable::try_new(config)?))?;
+        Ok(())
+    }
+}
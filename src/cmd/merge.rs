@@ -18,27 +18,63 @@
 // from https://raw.githubusercontent.com/apache/arrow-rs/master/parquet/src/bin/parquet-concat.rs
 
 use std::fs::File;
+use std::path::Path;
 use std::sync::Arc;
 
+use arrow_array::RecordBatch;
 use clap::Parser;
 use eyre::Report;
-use parquet::column::writer::ColumnCloseResult;
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use parquet::arrow::arrow_reader::ArrowReaderMetadata;
+use parquet::arrow::ArrowWriter;
 use parquet::errors::ParquetError;
-use parquet::file::properties::WriterProperties;
 use parquet::file::writer::SerializedFileWriter;
 
+use crate::cmd::dedupe::{is_sorted_on, Dedupe, Keep};
+use crate::cmd::inputs::{Input, InputArgs};
+use crate::cmd::utils::*;
+use crate::cmd::writer::{copy_row_group, encode_row_group, WriterArgs};
+
 #[derive(Debug, Parser)]
 /// merge parquet files by row groups
 pub struct Args {
     /// Path to output
     output: String,
 
-    /// Path to input files
+    /// Path to input files, directories or glob patterns
     input: Vec<String>,
+
+    #[command(flatten)]
+    inputs: InputArgs,
+
+    #[command(flatten)]
+    writer: WriterArgs,
+
+    #[arg(long, value_delimiter = ',')]
+    /// key columns to drop duplicate rows on, decodes and re-encodes the inputs
+    dedupe_on: Vec<String>,
+
+    #[arg(long, default_value = "first")]
+    /// row to keep for a duplicate key: first, last or max(<col>)
+    keep: Keep,
+
+    #[arg(long, default_value_t = false)]
+    /// treat the inputs as sorted on the dedupe keys and dedupe in one streaming pass
+    sorted: bool,
+
+    #[arg(long, default_value_t = 10_000_000)]
+    /// max distinct keys held in memory before spilling to disk
+    max_keys: usize,
+
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u64).range(1..))]
+    /// number of spill files when there are more than --max-keys keys
+    spill_partitions: u64,
 }
 
-pub fn merge_main(args: Args) -> eyre::Result<()> {
-    if args.input.is_empty() {
+pub fn merge_main(mut args: Args) -> eyre::Result<()> {
+    let inputs = args.inputs.open_inputs(&args.input)?;
+    if inputs.is_empty() {
         return Err(Report::from(ParquetError::General(
             "Must provide at least one input file".into(),
         )));
@@ -46,19 +82,9 @@ pub fn merge_main(args: Args) -> eyre::Result<()> {
 
     let output = File::create(&args.output)?;
 
-    let inputs = args
-        .input
-        .iter()
-        .map(|x| {
-            let reader = File::open(x).unwrap();
-            let metadata = parquet::file::footer::parse_metadata(&reader).unwrap();
-            (reader, metadata)
-        })
-        .collect::<Vec<_>>();
-
-    let expected = inputs[0].1.file_metadata().schema();
-    for (_, metadata) in inputs.iter().skip(1) {
-        let actual = metadata.file_metadata().schema();
+    let expected = inputs[0].metadata.file_metadata().schema();
+    for input in inputs.iter().skip(1) {
+        let actual = input.metadata.file_metadata().schema();
         if expected != actual {
             return Err(Report::from(ParquetError::General(format!(
                 "inputs must have the same schema, {expected:#?} vs {actual:#?}"
@@ -66,25 +92,33 @@ pub fn merge_main(args: Args) -> eyre::Result<()> {
         }
     }
 
-    let props = Arc::new(WriterProperties::builder().build());
-    let schema = inputs[0].1.file_metadata().schema_descr().root_schema_ptr();
-    let mut writer = SerializedFileWriter::new(output, schema, props)?;
-
-    for (input, metadata) in inputs {
-        for rg in metadata.row_groups() {
-            let mut rg_out = writer.next_row_group()?;
-            for column in rg.columns() {
-                let result = ColumnCloseResult {
-                    bytes_written: column.compressed_size() as _,
-                    rows_written: rg.num_rows() as _,
-                    metadata: column.clone(),
-                    bloom_filter: None,
-                    column_index: None,
-                    offset_index: None,
-                };
-                rg_out.append_column(&input, result)?;
+    if !args.dedupe_on.is_empty() {
+        args.writer.reencode = true;
+        return dedupe_merge(&args, inputs, output);
+    }
+
+    let props = Arc::new(
+        args.writer
+            .properties(&inputs.iter().map(|x| &x.metadata).collect::<Vec<_>>()),
+    );
+    let schema = inputs[0]
+        .metadata
+        .file_metadata()
+        .schema_descr()
+        .root_schema_ptr();
+    let mut writer = SerializedFileWriter::new(output, schema, props.clone())?;
+
+    for input in inputs {
+        if args.writer.reencode {
+            let arrow_metadata = ArrowReaderMetadata::load(&input.file, Default::default())?;
+            for idx in 0..input.metadata.num_row_groups() {
+                let batch = read_row_group(&input.file, &arrow_metadata, idx)?;
+                encode_row_group(&mut writer, &props, &batch, args.writer.jobs)?;
+            }
+        } else {
+            for rg in input.metadata.row_groups() {
+                copy_row_group(&mut writer, &input.file, rg)?;
             }
-            rg_out.close()?;
         }
     }
 
@@ -92,3 +126,49 @@ pub fn merge_main(args: Args) -> eyre::Result<()> {
 
     Ok(())
 }
+
+fn dedupe_merge(args: &Args, inputs: Vec<Input>, output: File) -> eyre::Result<()> {
+    let props = args
+        .writer
+        .properties(&inputs.iter().map(|x| &x.metadata).collect::<Vec<_>>());
+    let schema = ArrowReaderMetadata::load(&inputs[0].file, Default::default())?
+        .schema()
+        .clone();
+
+    let dedupe = Dedupe::try_new(schema.clone(), &args.dedupe_on, args.keep.clone())?;
+    let mut writer = ArrowWriter::try_new(output, schema, Some(props))?;
+    let mut emit = |batch: &RecordBatch| -> eyre::Result<()> { Ok(writer.write(batch)?) };
+
+    let sorted = args.sorted
+        || is_sorted_on(
+            &inputs.iter().map(|x| &x.metadata).collect::<Vec<_>>(),
+            &args.dedupe_on,
+        );
+    let sources = inputs.into_iter().map(|x| x.file).collect::<Vec<_>>();
+
+    let removed = if sorted {
+        info!(
+            "inputs are sorted on {:?}, dedupe in streaming mode",
+            args.dedupe_on
+        );
+        dedupe.sorted(&sources, &mut emit)?
+    } else {
+        // spill next to the output, which is expected to have room for the data
+        let output = Path::new(&args.output);
+        let tmp_dir = match output.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+        dedupe.hashed(
+            &sources,
+            args.max_keys,
+            tmp_dir,
+            args.spill_partitions as usize,
+            &mut emit,
+        )?
+    };
+    writer.close()?;
+
+    println!("removed {removed} duplicate rows");
+    Ok(())
+}
@@ -1,6 +1,18 @@
+pub(crate) mod apply;
 pub(crate) mod cat;
+mod dag;
+mod dedupe;
 pub mod df;
+mod inputs;
+mod listing;
 pub(crate) mod merge;
 pub(crate) mod meta;
+mod output;
+mod pattern;
+mod schema;
 pub mod split;
+mod stats;
+mod store;
 mod utils;
+mod vars;
+pub(crate) mod writer;
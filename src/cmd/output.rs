@@ -0,0 +1,854 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use arrow::array::Array;
+use arrow::datatypes::SchemaRef;
+use arrow::row::{OwnedRow, RowConverter, SortField};
+use arrow::util::display::array_value_to_string;
+use arrow_array::{ArrayRef, RecordBatch};
+use eyre::eyre;
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use parquet::arrow::ArrowWriter;
+use parquet::file::metadata::RowGroupMetaData;
+use parquet::file::properties::{WriterProperties, WriterPropertiesPtr};
+use parquet::file::writer::SerializedFileWriter;
+use parquet::format::KeyValue;
+use parquet::schema::types::TypePtr;
+
+use crate::cmd::writer::{copy_row_group, encode_row_group};
+
+/// output file name template, placeholders are `{idx}`, `{uuid}`, `{min}`,
+/// `{max}` and the values given when an output is opened, e.g. partition values.
+/// `{name:0N}` pads a value with zeros to N characters.
+#[derive(Debug, Clone)]
+pub struct NameTemplate {
+    template: String,
+}
+
+impl NameTemplate {
+    pub fn new(template: &str) -> eyre::Result<Self> {
+        let t = NameTemplate {
+            template: template.to_owned(),
+        };
+        t.placeholders()?;
+        Ok(t)
+    }
+
+    /// (name, width) of the placeholders
+    fn placeholders(&self) -> eyre::Result<Vec<(String, Option<usize>)>> {
+        let mut result = vec![];
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| eyre!("unclosed placeholder in {}", self.template))?;
+            let spec = &rest[start + 1..start + end];
+            let (name, width) = match spec.split_once(':') {
+                None => (spec, None),
+                Some((name, width)) => {
+                    let width = width
+                        .strip_prefix('0')
+                        .and_then(|w| w.parse::<usize>().ok())
+                        .ok_or_else(|| eyre!("invalid width {width} of {{{name}}}"))?;
+                    (name, Some(width))
+                }
+            };
+            result.push((name.to_owned(), width));
+            rest = &rest[start + end + 1..];
+        }
+        Ok(result)
+    }
+
+    /// fail on placeholders other than the built in ones and `values`
+    pub fn check(&self, values: &[String]) -> eyre::Result<()> {
+        for (name, _) in self.placeholders()? {
+            let known = ["idx", "uuid", "min", "max"].contains(&name.as_str())
+                || values.iter().any(|v| *v == name);
+            if !known {
+                return Err(eyre!(
+                    "unknown placeholder {{{name}}} in {}, expect one of idx, uuid, min, max{}",
+                    self.template,
+                    values.iter().map(|v| format!(", {v}")).collect::<String>()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn uses(&self, name: &str) -> bool {
+        self.placeholders()
+            .map(|p| p.iter().any(|(n, _)| n == name))
+            .unwrap_or(false)
+    }
+
+    /// render the template, placeholders missing in `values` are an error
+    pub fn render(&self, values: &[(String, String)]) -> eyre::Result<String> {
+        let mut result = String::new();
+        let mut rest = self.template.as_str();
+        for (name, width) in self.placeholders()? {
+            let start = rest.find('{').unwrap();
+            let end = start + rest[start..].find('}').unwrap();
+            result.push_str(&rest[..start]);
+
+            let value = values
+                .iter()
+                .find(|(k, _)| *k == name)
+                .map(|(_, v)| v.as_str())
+                .ok_or_else(|| eyre!("unknown placeholder {{{name}}} in {}", self.template))?;
+            match width {
+                Some(w) => result.push_str(&format!("{value:0>w$}")),
+                None => result.push_str(value),
+            }
+            rest = &rest[end + 1..];
+        }
+        result.push_str(rest);
+        Ok(result)
+    }
+}
+
+/// min and max of a column, compared in arrow row format
+struct Range {
+    converter: RowConverter,
+    min: Option<(OwnedRow, String)>,
+    max: Option<(OwnedRow, String)>,
+}
+
+impl Range {
+    fn update(&mut self, array: &ArrayRef) -> eyre::Result<()> {
+        let rows = self.converter.convert_columns(&[array.clone()])?;
+        for i in 0..array.len() {
+            if array.is_null(i) {
+                continue;
+            }
+            let row = rows.row(i);
+            if self.min.as_ref().map_or(true, |(m, _)| row < m.row()) {
+                self.min = Some((row.owned(), array_value_to_string(array, i)?));
+            }
+            if self.max.as_ref().map_or(true, |(m, _)| row > m.row()) {
+                self.max = Some((row.owned(), array_value_to_string(array, i)?));
+            }
+        }
+        Ok(())
+    }
+}
+
+enum Sink {
+    Chunk(SerializedFileWriter<File>),
+    Arrow(ArrowWriter<File>),
+    /// an arrow writer owned by an encoder thread, with its last known size
+    /// and the in-memory size of the batches queued for it
+    Worker {
+        id: usize,
+        jobs: SyncSender<Job>,
+        size: Arc<AtomicUsize>,
+        queued: Arc<AtomicUsize>,
+    },
+    /// --dry-run, only counts rows
+    DryRun,
+}
+
+/// work for an encoder thread
+enum Job {
+    Open(usize, ArrowWriter<File>, Arc<AtomicUsize>, Arc<AtomicUsize>),
+    Write(usize, RecordBatch),
+    Metadata(usize, KeyValue),
+    Close(usize, mpsc::Sender<eyre::Result<()>>),
+}
+
+struct Encoder {
+    jobs: SyncSender<Job>,
+    handle: JoinHandle<()>,
+}
+
+/// encode the outputs assigned to one thread, the first error of an
+/// output is reported when it is closed
+fn encode_outputs(jobs: Receiver<Job>) {
+    let mut writers = HashMap::new();
+    for job in jobs {
+        match job {
+            Job::Open(id, writer, size, queued) => {
+                writers.insert(id, (writer, size, queued, Ok(())));
+            }
+            Job::Write(id, batch) => {
+                if let Some((writer, size, queued, status)) = writers.get_mut(&id) {
+                    if status.is_ok() {
+                        *status = writer.write(&batch);
+                        let written = writer.bytes_written() + writer.in_progress_size();
+                        size.store(written, Ordering::Relaxed);
+                    }
+                    queued.fetch_sub(batch.get_array_memory_size(), Ordering::Relaxed);
+                }
+            }
+            Job::Metadata(id, kv) => {
+                if let Some((writer, _, _, _)) = writers.get_mut(&id) {
+                    writer.append_key_value_metadata(kv);
+                }
+            }
+            Job::Close(id, done) => {
+                if let Some((writer, _, _, status)) = writers.remove(&id) {
+                    let result = status.and_then(|_| writer.close().map(|_| ()));
+                    let _ = done.send(result.map_err(Into::into));
+                }
+            }
+        }
+    }
+}
+
+/// one output file being written
+pub struct Output {
+    values: Vec<(String, String)>,
+    /// final path, None if it depends on the min/max of the name column
+    path: Option<PathBuf>,
+    /// path written to, renamed to the final one on close
+    tmp: PathBuf,
+    sink: Sink,
+    /// index of the name column in the written batches
+    name_idx: Option<usize>,
+    range: Option<Range>,
+    rows: usize,
+    row_groups: usize,
+    /// bytes of copied row groups and estimated bytes of batches in dry runs
+    bytes: usize,
+    /// threads encoding the columns of a row group
+    jobs: usize,
+}
+
+impl Output {
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn row_groups(&self) -> usize {
+        self.row_groups
+    }
+
+    /// bytes written so far, including the buffered rows of arrow writers.
+    /// Batches still queued for an encoder thread count with their in-memory
+    /// size, so size limits are not overshot while the encoder catches up
+    pub fn size(&self) -> usize {
+        match &self.sink {
+            Sink::Arrow(w) => w.bytes_written() + w.in_progress_size(),
+            Sink::Chunk(w) => w.bytes_written(),
+            Sink::Worker { size, queued, .. } => {
+                size.load(Ordering::Relaxed) + queued.load(Ordering::Relaxed)
+            }
+            Sink::DryRun => self.bytes,
+        }
+    }
+
+    /// track the min/max of the name column for row groups copied without decoding
+    pub fn track(&mut self, array: &ArrayRef) -> eyre::Result<()> {
+        match &mut self.range {
+            Some(range) => range.update(array),
+            None => Ok(()),
+        }
+    }
+
+    fn track_batch(&mut self, batch: &RecordBatch) -> eyre::Result<()> {
+        match self.name_idx {
+            Some(i) => self.track(batch.column(i)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn copy_row_group(&mut self, reader: &File, rg: &RowGroupMetaData) -> eyre::Result<()> {
+        match &mut self.sink {
+            Sink::Chunk(w) => copy_row_group(w, reader, rg)?,
+            Sink::Arrow(_) | Sink::Worker { .. } => {
+                return Err(eyre!("can not copy row groups to an arrow writer"))
+            }
+            Sink::DryRun => self.bytes += rg.compressed_size() as usize,
+        }
+        self.rows += rg.num_rows() as usize;
+        self.row_groups += 1;
+        Ok(())
+    }
+
+    pub fn encode_row_group(
+        &mut self,
+        props: &WriterPropertiesPtr,
+        batch: &RecordBatch,
+    ) -> eyre::Result<()> {
+        match &mut self.sink {
+            Sink::Chunk(w) => encode_row_group(w, props, batch, self.jobs)?,
+            Sink::Arrow(_) | Sink::Worker { .. } => {
+                return Err(eyre!("can not encode row groups to an arrow writer"))
+            }
+            Sink::DryRun => self.bytes += batch.get_array_memory_size(),
+        }
+        self.track_batch(batch)?;
+        self.rows += batch.num_rows();
+        self.row_groups += 1;
+        Ok(())
+    }
+
+    pub fn write(&mut self, batch: &RecordBatch) -> eyre::Result<()> {
+        match &mut self.sink {
+            Sink::Arrow(w) => w.write(batch)?,
+            Sink::Worker {
+                id, jobs, queued, ..
+            } => {
+                queued.fetch_add(batch.get_array_memory_size(), Ordering::Relaxed);
+                jobs.send(Job::Write(*id, batch.clone()))
+                    .map_err(|_| eyre!("encoder thread stopped"))?
+            }
+            Sink::Chunk(_) => return Err(eyre!("can not write batches to a chunk writer")),
+            Sink::DryRun => self.bytes += batch.get_array_memory_size(),
+        }
+        self.track_batch(batch)?;
+        self.rows += batch.num_rows();
+        Ok(())
+    }
+
+    pub fn append_key_value_metadata(&mut self, kv: KeyValue) -> eyre::Result<()> {
+        match &mut self.sink {
+            Sink::Arrow(w) => w.append_key_value_metadata(kv),
+            Sink::Chunk(w) => w.append_key_value_metadata(kv),
+            Sink::Worker { id, jobs, .. } => jobs
+                .send(Job::Metadata(*id, kv))
+                .map_err(|_| eyre!("encoder thread stopped"))?,
+            Sink::DryRun => {}
+        }
+        Ok(())
+    }
+}
+
+/// a file written by [`Outputs`]
+pub struct Written {
+    pub path: String,
+    pub rows: usize,
+    pub values: Vec<(String, String)>,
+}
+
+/// creates, names and finalizes output files. Outputs are written to temporary
+/// files renamed to their final path in [`Outputs::finish`], existing files are
+/// only replaced with `force`. With more than one job arrow outputs are encoded
+/// by a pool of threads, names are still given in the order outputs are opened.
+/// Temporary files left when it is dropped, e.g. on an error, are removed
+pub struct Outputs {
+    template: NameTemplate,
+    /// directory of temporary files
+    base: PathBuf,
+    name_column: Option<String>,
+    dry_run: bool,
+    /// replace existing files
+    force: bool,
+    jobs: usize,
+    encoders: Vec<Encoder>,
+    /// arrow outputs handed to encoders so far
+    opened: usize,
+    counters: HashMap<Vec<(String, String)>, usize>,
+    /// final paths given out so far, two outputs must not share one
+    paths: HashSet<PathBuf>,
+    /// temporary files not yet renamed to their final path
+    tmp_files: HashSet<PathBuf>,
+    /// closed outputs, waiting for their encoder when they have one
+    pending: Vec<(Output, Option<Receiver<eyre::Result<()>>>)>,
+    written: Vec<Written>,
+}
+
+impl Outputs {
+    /// `values` are the names of the values outputs are opened with
+    pub fn new(
+        template: NameTemplate,
+        values: &[String],
+        base: &Path,
+        name_column: Option<String>,
+        dry_run: bool,
+        force: bool,
+        jobs: usize,
+    ) -> eyre::Result<Self> {
+        template.check(values)?;
+        if (template.uses("min") || template.uses("max")) && name_column.is_none() {
+            return Err(eyre!("{{min}} and {{max}} require a name column"));
+        }
+        Ok(Outputs {
+            template,
+            base: base.to_owned(),
+            name_column,
+            dry_run,
+            force,
+            jobs: jobs.max(1),
+            encoders: vec![],
+            opened: 0,
+            counters: HashMap::new(),
+            paths: HashSet::new(),
+            tmp_files: HashSet::new(),
+            pending: vec![],
+            written: vec![],
+        })
+    }
+
+    pub fn name_column(&self) -> Option<&str> {
+        self.name_column.as_deref()
+    }
+
+    pub fn written(&self) -> &[Written] {
+        &self.written
+    }
+
+    /// whether each opened output gets a new name, even for the same values
+    pub fn unique_names(&self) -> bool {
+        self.template.uses("idx") || self.template.uses("uuid")
+    }
+
+    /// whether output names depend on the min/max of the name column
+    pub fn needs_range(&self) -> bool {
+        self.template.uses("min") || self.template.uses("max")
+    }
+
+    /// allocate the index and path of a new output with `values`
+    fn prepare(&mut self, values: &[(String, String)], schema: &SchemaRef) -> eyre::Result<Output> {
+        let counter = self.counters.entry(values.to_vec()).or_insert(0);
+        let idx = *counter;
+        *counter += 1;
+
+        let mut values = values.to_vec();
+        values.push(("idx".to_owned(), idx.to_string()));
+        values.push(("uuid".to_owned(), uuid::Uuid::new_v4().to_string()));
+
+        // known paths get their temporary file next to them, the rename stays
+        // in one directory
+        let tmp_name = format!(".pp-tmp-{}.parquet", uuid::Uuid::new_v4());
+        let (path, tmp) = match self.needs_range() {
+            true => (None, self.base.join(tmp_name)),
+            false => {
+                let path = PathBuf::from(self.template.render(&values)?);
+                self.claim(&path)?;
+                let tmp = path.with_file_name(tmp_name);
+                (Some(path), tmp)
+            }
+        };
+
+        let name_idx = match (&self.name_column, self.needs_range()) {
+            (Some(c), true) => Some(schema.index_of(c)?),
+            _ => None,
+        };
+        let range = match name_idx {
+            Some(i) => Some(Range {
+                converter: RowConverter::new(vec![SortField::new(
+                    schema.field(i).data_type().clone(),
+                )])?,
+                min: None,
+                max: None,
+            }),
+            None => None,
+        };
+
+        if !self.dry_run {
+            if let Some(dir) = tmp.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            self.tmp_files.insert(tmp.clone());
+        }
+
+        Ok(Output {
+            values,
+            path,
+            tmp,
+            sink: Sink::DryRun,
+            name_idx,
+            range,
+            rows: 0,
+            row_groups: 0,
+            bytes: 0,
+            jobs: self.jobs,
+        })
+    }
+
+    /// reserve the final path of an output, a template without {idx} or
+    /// {uuid} may render the same path twice which would overwrite a file
+    fn claim(&mut self, path: &Path) -> eyre::Result<()> {
+        if !self.paths.insert(path.to_owned()) {
+            return Err(eyre!(
+                "{path:?} is written twice, add {{idx}} or {{uuid}} to the name template"
+            ));
+        }
+        if !self.force && path.exists() {
+            return Err(eyre!("{path:?} exists, use --force to replace it"));
+        }
+        Ok(())
+    }
+
+    /// open an output receiving whole row groups
+    pub fn open_chunk(
+        &mut self,
+        values: &[(String, String)],
+        arrow_schema: &SchemaRef,
+        schema: TypePtr,
+        props: WriterPropertiesPtr,
+    ) -> eyre::Result<Output> {
+        let mut output = self.prepare(values, arrow_schema)?;
+        if !self.dry_run {
+            let file = File::create(&output.tmp)?;
+            output.sink = Sink::Chunk(SerializedFileWriter::new(file, schema, props)?);
+        }
+        Ok(output)
+    }
+
+    /// open an output receiving record batches
+    pub fn open_arrow(
+        &mut self,
+        values: &[(String, String)],
+        schema: &SchemaRef,
+        props: WriterProperties,
+    ) -> eyre::Result<Output> {
+        let mut output = self.prepare(values, schema)?;
+        if self.dry_run {
+            return Ok(output);
+        }
+
+        let file = File::create(&output.tmp)?;
+        let writer = ArrowWriter::try_new(file, schema.clone(), Some(props))?;
+        if self.jobs == 1 {
+            output.sink = Sink::Arrow(writer);
+            return Ok(output);
+        }
+
+        let id = self.opened;
+        self.opened += 1;
+        if self.encoders.len() < self.jobs {
+            let (jobs, rx) = mpsc::sync_channel(16);
+            let handle = std::thread::spawn(move || encode_outputs(rx));
+            self.encoders.push(Encoder { jobs, handle });
+        }
+        let jobs = self.encoders[id % self.jobs].jobs.clone();
+        let size = Arc::new(AtomicUsize::new(0));
+        let queued = Arc::new(AtomicUsize::new(0));
+        jobs.send(Job::Open(id, writer, size.clone(), queued.clone()))
+            .map_err(|_| eyre!("encoder thread stopped"))?;
+        output.sink = Sink::Worker {
+            id,
+            jobs,
+            size,
+            queued,
+        };
+        Ok(output)
+    }
+
+    /// close `output`, it gets its final name in [`Outputs::finish`]
+    pub fn close(&mut self, mut output: Output) -> eyre::Result<()> {
+        let mut done = None;
+        match std::mem::replace(&mut output.sink, Sink::DryRun) {
+            Sink::Chunk(w) => {
+                w.close()?;
+            }
+            Sink::Arrow(w) => {
+                w.close()?;
+            }
+            Sink::Worker { id, jobs, .. } => {
+                let (tx, rx) = mpsc::channel();
+                jobs.send(Job::Close(id, tx))
+                    .map_err(|_| eyre!("encoder thread stopped"))?;
+                done = Some(rx);
+            }
+            Sink::DryRun => {}
+        }
+        self.pending.push((output, done));
+        Ok(())
+    }
+
+    /// wait for the encoders, move the closed outputs to their final names
+    /// and list them in dry runs
+    pub fn finish(&mut self) -> eyre::Result<()> {
+        for (output, done) in std::mem::take(&mut self.pending) {
+            if let Some(done) = done {
+                done.recv().map_err(|_| eyre!("encoder thread stopped"))??;
+            }
+            self.rename(output)?;
+        }
+        for encoder in self.encoders.drain(..) {
+            drop(encoder.jobs);
+            encoder
+                .handle
+                .join()
+                .map_err(|_| eyre!("encoder thread panicked"))?;
+        }
+
+        if self.dry_run {
+            for w in &self.written {
+                println!("{}: {} rows", w.path, w.rows);
+            }
+        }
+        Ok(())
+    }
+
+    fn rename(&mut self, mut output: Output) -> eyre::Result<()> {
+        let path = match output.path.take() {
+            Some(path) => {
+                if !self.dry_run {
+                    std::fs::rename(&output.tmp, &path)?;
+                    self.tmp_files.remove(&output.tmp);
+                }
+                path
+            }
+            None => {
+                let range = output.range.take();
+                let (min, max) = match range {
+                    Some(Range { min, max, .. }) => (
+                        min.map(|(_, v)| v).unwrap_or_else(|| "null".to_owned()),
+                        max.map(|(_, v)| v).unwrap_or_else(|| "null".to_owned()),
+                    ),
+                    None => ("null".to_owned(), "null".to_owned()),
+                };
+                output.values.push(("min".to_owned(), min));
+                output.values.push(("max".to_owned(), max));
+
+                let path = PathBuf::from(self.template.render(&output.values)?);
+                self.claim(&path)?;
+                if !self.dry_run {
+                    if let Some(dir) = path.parent() {
+                        std::fs::create_dir_all(dir)?;
+                    }
+                    std::fs::rename(&output.tmp, &path)?;
+                    self.tmp_files.remove(&output.tmp);
+                }
+                path
+            }
+        };
+
+        debug!("{path:?}: {} rows", output.rows);
+        self.written.push(Written {
+            path: path.to_string_lossy().to_string(),
+            rows: output.rows,
+            values: output.values,
+        });
+        Ok(())
+    }
+}
+
+impl Drop for Outputs {
+    fn drop(&mut self) {
+        for tmp in self.tmp_files.drain() {
+            if let Err(e) = std::fs::remove_file(&tmp) {
+                warn!("can not remove {tmp:?}: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::Int64Array;
+    use arrow::datatypes::{DataType, Field, Schema};
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    use super::*;
+    use crate::cmd::utils::TempDir;
+
+    fn values(v: &[(&str, &str)]) -> Vec<(String, String)> {
+        v.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_render_template() {
+        let t = NameTemplate::new("{date}/{device}_{idx:05}.parquet").unwrap();
+        let v = values(&[("date", "2024-03-01"), ("device", "d1"), ("idx", "7")]);
+        assert_eq!(t.render(&v).unwrap(), "2024-03-01/d1_00007.parquet");
+    }
+
+    #[test]
+    fn test_render_template_unknown_placeholder() {
+        let t = NameTemplate::new("{region}.parquet").unwrap();
+        assert!(t.render(&values(&[("idx", "0")])).is_err());
+    }
+
+    #[test]
+    fn test_bad_template() {
+        assert!(NameTemplate::new("{idx").is_err());
+        assert!(NameTemplate::new("{idx:x}").is_err());
+    }
+
+    #[test]
+    fn test_check_template() {
+        let t = NameTemplate::new("{date}/{idx}_{min}.parquet").unwrap();
+        assert!(t.check(&["date".to_owned()]).is_ok());
+        assert!(t.check(&[]).is_err());
+    }
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)]))
+    }
+
+    fn batch(ids: Vec<i64>) -> RecordBatch {
+        RecordBatch::try_new(schema(), vec![Arc::new(Int64Array::from(ids))]).unwrap()
+    }
+
+    fn outputs(dir: &TempDir, template: &str, name_column: Option<&str>) -> Outputs {
+        outputs_with_jobs(dir, template, name_column, 1)
+    }
+
+    fn outputs_with_jobs(
+        dir: &TempDir,
+        template: &str,
+        name_column: Option<&str>,
+        jobs: usize,
+    ) -> Outputs {
+        let template = dir.path().join(template).to_string_lossy().to_string();
+        Outputs::new(
+            NameTemplate::new(&template).unwrap(),
+            &["part".to_owned()],
+            dir.path(),
+            name_column.map(|c| c.to_owned()),
+            false,
+            false,
+            jobs,
+        )
+        .unwrap()
+    }
+
+    /// (file name, rows) of the files in `dir`
+    fn files(dir: &TempDir) -> Vec<(String, i64)> {
+        let mut files = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| {
+                let path = e.unwrap().path();
+                let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+                let rows = reader.metadata().file_metadata().num_rows();
+                (
+                    path.file_name().unwrap().to_string_lossy().to_string(),
+                    rows,
+                )
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    /// write the batches of `ids` to one output each
+    fn write(outputs: &mut Outputs, ids: Vec<Vec<i64>>) -> eyre::Result<()> {
+        for ids in ids {
+            let part = vec![("part".to_owned(), "p".to_owned())];
+            let mut output = outputs.open_arrow(&part, &schema(), WriterProperties::default())?;
+            output.write(&batch(ids))?;
+            outputs.close(output)?;
+        }
+        outputs.finish()
+    }
+
+    #[test]
+    fn test_outputs() {
+        let dir = TempDir::new(std::env::temp_dir(), "pp-test").unwrap();
+        let mut outputs = outputs(&dir, "{part}_{idx:02}.parquet", None);
+        write(&mut outputs, vec![vec![1, 2, 3], vec![4, 5]]).unwrap();
+
+        let expected = vec![
+            ("p_00.parquet".to_owned(), 3),
+            ("p_01.parquet".to_owned(), 2),
+        ];
+        assert_eq!(files(&dir), expected);
+        assert_eq!(outputs.written().len(), 2);
+        assert_eq!(outputs.written()[1].rows, 2);
+    }
+
+    #[test]
+    fn test_outputs_min_max() {
+        let dir = TempDir::new(std::env::temp_dir(), "pp-test").unwrap();
+        let mut outputs = outputs(&dir, "{min}-{max}.parquet", Some("id"));
+        write(&mut outputs, vec![vec![3, 1, 2], vec![7, 9]]).unwrap();
+
+        let expected = vec![("1-3.parquet".to_owned(), 3), ("7-9.parquet".to_owned(), 2)];
+        assert_eq!(files(&dir), expected);
+    }
+
+    #[test]
+    fn test_outputs_same_path() {
+        let dir = TempDir::new(std::env::temp_dir(), "pp-test").unwrap();
+        let mut outputs = outputs(&dir, "{part}.parquet", None);
+        assert!(write(&mut outputs, vec![vec![1], vec![2]]).is_err());
+        drop(outputs);
+        // the first output was not finished, nothing is left
+        assert_eq!(files(&dir), vec![]);
+    }
+
+    #[test]
+    fn test_outputs_existing_file() {
+        let dir = TempDir::new(std::env::temp_dir(), "pp-test").unwrap();
+        let mut outputs = outputs(&dir, "{part}.parquet", None);
+        write(&mut outputs, vec![vec![1, 2]]).unwrap();
+        drop(outputs);
+
+        let mut outputs = self::outputs(&dir, "{part}.parquet", None);
+        assert!(write(&mut outputs, vec![vec![3]]).is_err());
+        drop(outputs);
+        assert_eq!(files(&dir), vec![("p.parquet".to_owned(), 2)]);
+
+        let template = dir.path().join("{part}.parquet");
+        let mut outputs = Outputs::new(
+            NameTemplate::new(&template.to_string_lossy()).unwrap(),
+            &["part".to_owned()],
+            dir.path(),
+            None,
+            false,
+            true,
+            1,
+        )
+        .unwrap();
+        write(&mut outputs, vec![vec![3]]).unwrap();
+        assert_eq!(files(&dir), vec![("p.parquet".to_owned(), 1)]);
+    }
+
+    #[test]
+    fn test_outputs_error_leaves_no_files() {
+        let dir = TempDir::new(std::env::temp_dir(), "pp-test").unwrap();
+        let mut outputs = outputs(&dir, "{part}_{idx}.parquet", None);
+        let part = vec![("part".to_owned(), "p".to_owned())];
+        let mut output = outputs
+            .open_arrow(&part, &schema(), WriterProperties::default())
+            .unwrap();
+        output.write(&batch(vec![1, 2])).unwrap();
+        // a batch of another schema fails the write half way
+        let other = Arc::new(Schema::new(vec![Field::new("id", DataType::Utf8, false)]));
+        let bad = RecordBatch::try_new(
+            other,
+            vec![Arc::new(arrow::array::StringArray::from(vec!["a"]))],
+        )
+        .unwrap();
+        assert!(output.write(&bad).is_err());
+        drop(output);
+        drop(outputs);
+        assert_eq!(files(&dir), vec![]);
+    }
+
+    #[test]
+    fn test_outputs_same_min_max_path() {
+        let dir = TempDir::new(std::env::temp_dir(), "pp-test").unwrap();
+        let mut outputs = outputs(&dir, "{min}.parquet", Some("id"));
+        assert!(write(&mut outputs, vec![vec![1, 2], vec![1, 3]]).is_err());
+        drop(outputs);
+        // the temporary file of the second output is removed
+        assert_eq!(files(&dir), vec![("1.parquet".to_owned(), 2)]);
+    }
+
+    #[test]
+    fn test_outputs_jobs() {
+        let ids = (0..5)
+            .map(|i| (i * 100..i * 100 + 50 + i * 10).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let written = |jobs: usize| {
+            let dir = TempDir::new(std::env::temp_dir(), "pp-test").unwrap();
+            let mut outputs = outputs_with_jobs(&dir, "{part}_{idx}.parquet", None, jobs);
+            write(&mut outputs, ids.clone()).unwrap();
+            let names = files(&dir);
+            let contents = names
+                .iter()
+                .map(|(name, _)| std::fs::read(dir.path().join(name)).unwrap())
+                .collect::<Vec<_>>();
+            (names, contents)
+        };
+
+        let expected = written(1);
+        assert_eq!(expected.0.len(), 5);
+        for jobs in [2, 4] {
+            assert_eq!(written(jobs), expected, "jobs {jobs}");
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use regex::Regex;
+
+/// a column name or a family of columns in df configs:
+/// `collect_time`, a range `dp_{0001..0500}`, a glob `dp_*` or a regex `re:^dp_\d+$`
+#[derive(Debug, Clone)]
+pub enum ColumnPattern {
+    /// a name, or the names of a range
+    Exact(Vec<String>),
+    Glob(glob::Pattern),
+    Regex(Regex),
+}
+
+impl ColumnPattern {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        if let Some(re) = value.strip_prefix("re:") {
+            return Regex::new(re)
+                .map(ColumnPattern::Regex)
+                .map_err(|e| format!("invalid column regex {re}: {e}"));
+        }
+        if value.contains("..") && value.contains('{') {
+            return expand_range(value).map(ColumnPattern::Exact);
+        }
+        if value.contains(['*', '?', '[']) {
+            return glob::Pattern::new(value)
+                .map(ColumnPattern::Glob)
+                .map_err(|e| format!("invalid column glob {value}: {e}"));
+        }
+        Ok(ColumnPattern::Exact(vec![value.to_owned()]))
+    }
+
+    /// the listed names of exact patterns and ranges
+    pub fn names(&self) -> Option<&[String]> {
+        match self {
+            ColumnPattern::Exact(names) => Some(names),
+            _ => None,
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            ColumnPattern::Exact(names) => names.iter().any(|n| n == name),
+            ColumnPattern::Glob(p) => p.matches(name),
+            ColumnPattern::Regex(re) => re.is_match(name),
+        }
+    }
+
+    /// sort key of [`matching`], lower is more specific
+    fn specificity(&self) -> (u8, usize) {
+        match self {
+            ColumnPattern::Exact(names) if names.len() == 1 => (0, 0),
+            ColumnPattern::Exact(names) => (1, names.len()),
+            ColumnPattern::Glob(p) => (2, usize::MAX - literal_len(p.as_str())),
+            ColumnPattern::Regex(_) => (3, 0),
+        }
+    }
+}
+
+/// indexes of the patterns matching `name`, most specific first: exact names,
+/// then ranges from the shortest, globs with the most literal characters and
+/// regexes. Equally specific patterns keep their listed order
+pub fn matching(patterns: &[ColumnPattern], name: &str) -> Vec<usize> {
+    let mut result = patterns
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| p.matches(name))
+        .map(|(i, p)| (p.specificity(), i))
+        .collect::<Vec<_>>();
+    result.sort_by_key(|(rank, _)| *rank);
+    result.into_iter().map(|(_, i)| i).collect()
+}
+
+/// characters of a glob outside of wildcards and `[...]` classes
+fn literal_len(glob: &str) -> usize {
+    let mut len = 0;
+    let mut class = false;
+    for c in glob.chars() {
+        match c {
+            '[' if !class => class = true,
+            ']' if class => class = false,
+            '*' | '?' => {}
+            _ if !class => len += 1,
+            _ => {}
+        }
+    }
+    len
+}
+
+/// expand `prefix{start..end}suffix`, zero padded to the width of `start`
+fn expand_range(value: &str) -> Result<Vec<String>, String> {
+    let start = value
+        .find('{')
+        .ok_or_else(|| format!("invalid range {value}"))?;
+    let end = start
+        + value[start..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed range in {value}"))?;
+    let (first, last) = value[start + 1..end]
+        .split_once("..")
+        .ok_or_else(|| format!("invalid range in {value}, expect {{first..last}}"))?;
+
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<u64>()
+            .map_err(|_| format!("invalid range bound {v} in {value}"))
+    };
+    let (from, to) = (parse(first)?, parse(last)?);
+    if from > to {
+        return Err(format!("empty range in {value}"));
+    }
+    let width = first.trim().len();
+
+    let mut names = vec![];
+    for i in from..=to {
+        let name = format!("{}{i:0width$}{}", &value[..start], &value[end + 1..]);
+        // more ranges in the suffix
+        match name[start..].contains("..") && name[start..].contains('{') {
+            true => names.extend(expand_range(&name)?),
+            false => names.push(name),
+        }
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_range() {
+        let names = expand_range("dp_{0001..0500}").unwrap();
+        assert_eq!(names.len(), 500);
+        assert_eq!(names[0], "dp_0001");
+        assert_eq!(names[499], "dp_0500");
+        assert_eq!(
+            expand_range("c{8..10}_x").unwrap(),
+            vec!["c8_x", "c9_x", "c10_x"]
+        );
+        assert!(expand_range("dp_{5..1}").is_err());
+    }
+
+    #[test]
+    fn test_matching_precedence() {
+        let patterns = ["dp_*", "re:^dp_\\d+$", "dp_{0001..0003}", "dp_0002"]
+            .iter()
+            .map(|p| ColumnPattern::parse(p).unwrap())
+            .collect::<Vec<_>>();
+        // the exact name wins over the range listed before it
+        assert_eq!(matching(&patterns, "dp_0002"), vec![3, 2, 0, 1]);
+        assert_eq!(matching(&patterns, "dp_0009"), vec![0, 1]);
+        assert_eq!(matching(&patterns, "dp_x"), vec![0]);
+        assert!(matching(&patterns, "collect_time").is_empty());
+    }
+
+    #[test]
+    fn test_matching_most_specific() {
+        let patterns = [
+            "*_time",
+            "re:time$",
+            "dp_{0001..0500}",
+            "update_*",
+            "dp_{0001..0010}",
+        ]
+        .iter()
+        .map(|p| ColumnPattern::parse(p).unwrap())
+        .collect::<Vec<_>>();
+        assert_eq!(matching(&patterns, "dp_0005"), vec![4, 2]);
+        assert_eq!(matching(&patterns, "update_time"), vec![3, 0, 1]);
+        assert_eq!(literal_len("dp_[0-9]?*"), 3);
+    }
+}
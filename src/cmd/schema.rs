@@ -0,0 +1,364 @@
+use std::sync::Arc;
+
+use datafusion::arrow::datatypes::{
+    DataType, Field, Fields, IntervalUnit, TimeUnit, DECIMAL128_MAX_PRECISION,
+    DECIMAL256_MAX_PRECISION,
+};
+
+/// parse an Arrow type like `Int64`, `Decimal128(38, 6)`, `Timestamp(Microsecond, "UTC")`
+/// or `List<Utf8>`, including the form arrow displays types in. Names are case
+/// insensitive and common SQL names such as `bigint` or `varchar` are accepted.
+/// `timestamp` and `decimal` without parameters are Timestamp(Millisecond) and
+/// Decimal128(20, 10)
+pub fn parse_type(value: &str) -> Result<DataType, String> {
+    let mut parser = TypeParser {
+        input: value,
+        pos: 0,
+    };
+    let t = parser.data_type()?;
+    parser.skip_spaces();
+    if parser.pos < value.len() {
+        return Err(parser.error("unexpected trailing input"));
+    }
+    Ok(t)
+}
+
+struct TypeParser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> TypeParser<'a> {
+    fn error(&self, message: &str) -> String {
+        format!(
+            "invalid type {:?} at position {}: {message}",
+            self.input, self.pos
+        )
+    }
+
+    fn skip_spaces(&mut self) {
+        let rest = &self.input[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_spaces();
+        self.input[self.pos..].chars().next()
+    }
+
+    /// consume `c` if it is next
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.eat(c) {
+            true => Ok(()),
+            false => Err(self.error(&format!("expect '{c}'"))),
+        }
+    }
+
+    fn word(&mut self) -> Result<&'a str, String> {
+        self.skip_spaces();
+        let input = self.input;
+        let rest = &input[self.pos..];
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.error("expect a name"));
+        }
+        self.pos += len;
+        Ok(&rest[..len])
+    }
+
+    fn number<T: std::str::FromStr>(&mut self) -> Result<T, String> {
+        let negative = self.eat('-');
+        let digits = self.word()?;
+        let value = match negative {
+            true => format!("-{digits}"),
+            false => digits.to_owned(),
+        };
+        value
+            .parse::<T>()
+            .map_err(|_| self.error(&format!("expect a number, got {value}")))
+    }
+
+    /// a quoted string, `Some("...")` or `None`
+    fn time_zone(&mut self) -> Result<Option<Arc<str>>, String> {
+        match self.peek() {
+            Some('"') | Some('\'') => {
+                let quote = self.peek().unwrap();
+                self.pos += 1;
+                let input = self.input;
+                let rest = &input[self.pos..];
+                let end = rest
+                    .find(quote)
+                    .ok_or_else(|| self.error("unclosed string"))?;
+                self.pos += end + 1;
+                Ok(Some(rest[..end].into()))
+            }
+            _ => match self.word()?.to_lowercase().as_str() {
+                "none" => Ok(None),
+                "some" => {
+                    self.expect('(')?;
+                    let tz = self.time_zone()?;
+                    self.expect(')')?;
+                    Ok(tz)
+                }
+                tz => Err(self.error(&format!("expect a quoted time zone, got {tz}"))),
+            },
+        }
+    }
+
+    fn time_unit(&mut self) -> Result<TimeUnit, String> {
+        match self.word()?.to_lowercase().as_str() {
+            "second" | "s" => Ok(TimeUnit::Second),
+            "millisecond" | "ms" => Ok(TimeUnit::Millisecond),
+            "microsecond" | "us" => Ok(TimeUnit::Microsecond),
+            "nanosecond" | "ns" => Ok(TimeUnit::Nanosecond),
+            v => Err(self.error(&format!("unknown time unit {v}"))),
+        }
+    }
+
+    /// `<T>` or `(T)`
+    fn item_type(&mut self) -> Result<DataType, String> {
+        let close = match self.eat('<') {
+            true => '>',
+            false => {
+                self.expect('(')?;
+                ')'
+            }
+        };
+        let t = self.data_type()?;
+        self.expect(close)?;
+        Ok(t)
+    }
+
+    fn data_type(&mut self) -> Result<DataType, String> {
+        let name = self.word()?.to_lowercase();
+        let t = match name.as_str() {
+            "null" => DataType::Null,
+            "boolean" | "bool" => DataType::Boolean,
+            "int8" | "tinyint" => DataType::Int8,
+            "int16" | "smallint" => DataType::Int16,
+            "int32" | "int" | "integer" => DataType::Int32,
+            "int64" | "bigint" | "long" => DataType::Int64,
+            "uint8" => DataType::UInt8,
+            "uint16" => DataType::UInt16,
+            "uint32" => DataType::UInt32,
+            "uint64" => DataType::UInt64,
+            "float16" => DataType::Float16,
+            "float32" | "float" | "real" => DataType::Float32,
+            "float64" | "double" => DataType::Float64,
+            "utf8" | "string" | "varchar" | "text" => DataType::Utf8,
+            "largeutf8" => DataType::LargeUtf8,
+            "binary" | "bytea" => DataType::Binary,
+            "largebinary" => DataType::LargeBinary,
+            "date32" | "date" => DataType::Date32,
+            "date64" => DataType::Date64,
+            "timestamp" => match self.eat('(') {
+                false => DataType::Timestamp(TimeUnit::Millisecond, None),
+                true => {
+                    let unit = self.time_unit()?;
+                    let tz = match self.eat(',') {
+                        true => self.time_zone()?,
+                        false => None,
+                    };
+                    self.expect(')')?;
+                    DataType::Timestamp(unit, tz)
+                }
+            },
+            "time32" | "time64" | "duration" => {
+                self.expect('(')?;
+                let unit = self.time_unit()?;
+                self.expect(')')?;
+                match name.as_str() {
+                    "time32" => DataType::Time32(unit),
+                    "time64" => DataType::Time64(unit),
+                    _ => DataType::Duration(unit),
+                }
+            }
+            "interval" => {
+                self.expect('(')?;
+                let unit = match self.word()?.to_lowercase().as_str() {
+                    "yearmonth" => IntervalUnit::YearMonth,
+                    "daytime" => IntervalUnit::DayTime,
+                    "monthdaynano" => IntervalUnit::MonthDayNano,
+                    v => return Err(self.error(&format!("unknown interval unit {v}"))),
+                };
+                self.expect(')')?;
+                DataType::Interval(unit)
+            }
+            "decimal" | "decimal128" | "decimal256" | "numeric" => {
+                let (precision, scale) = match self.eat('(') {
+                    false if name == "decimal" => (20, 10),
+                    false => return Err(self.error("expect (precision, scale)")),
+                    true => {
+                        let precision = self.number::<u8>()?;
+                        let scale = match self.eat(',') {
+                            true => self.number::<i8>()?,
+                            false => 0,
+                        };
+                        self.expect(')')?;
+                        (precision, scale)
+                    }
+                };
+                let max = match name.as_str() {
+                    "decimal256" => DECIMAL256_MAX_PRECISION,
+                    _ => DECIMAL128_MAX_PRECISION,
+                };
+                // arrow only checks these on arrays
+                if precision == 0 || precision > max || scale > precision as i8 {
+                    return Err(self.error(&format!(
+                        "precision must be 1 to {max} and at least the scale"
+                    )));
+                }
+                match name.as_str() {
+                    "decimal256" => DataType::Decimal256(precision, scale),
+                    _ => DataType::Decimal128(precision, scale),
+                }
+            }
+            "fixedsizebinary" => {
+                self.expect('(')?;
+                let size = self.number::<i32>()?;
+                self.expect(')')?;
+                DataType::FixedSizeBinary(size)
+            }
+            "list" => DataType::List(Arc::new(Field::new("item", self.item_type()?, true))),
+            "largelist" => {
+                DataType::LargeList(Arc::new(Field::new("item", self.item_type()?, true)))
+            }
+            "fixedsizelist" => {
+                let close = match self.eat('<') {
+                    true => '>',
+                    false => {
+                        self.expect('(')?;
+                        ')'
+                    }
+                };
+                let item = self.data_type()?;
+                self.expect(',')?;
+                let size = self.number::<i32>()?;
+                self.expect(close)?;
+                DataType::FixedSizeList(Arc::new(Field::new("item", item, true)), size)
+            }
+            "dictionary" => {
+                self.expect('(')?;
+                let key = self.data_type()?;
+                self.expect(',')?;
+                let value = self.data_type()?;
+                self.expect(')')?;
+                DataType::Dictionary(Box::new(key), Box::new(value))
+            }
+            "struct" => {
+                let close = match self.eat('<') {
+                    true => '>',
+                    false => {
+                        self.expect('(')?;
+                        ')'
+                    }
+                };
+                let mut fields = vec![];
+                while !self.eat(close) {
+                    if !fields.is_empty() {
+                        self.expect(',')?;
+                    }
+                    let name = self.word()?.to_owned();
+                    self.expect(':')?;
+                    fields.push(Field::new(name, self.data_type()?, true));
+                }
+                DataType::Struct(Fields::from(fields))
+            }
+            v => return Err(self.error(&format!("unknown type {v}"))),
+        };
+        Ok(t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_type() {
+        assert_eq!(parse_type("Int64"), Ok(DataType::Int64));
+        assert_eq!(parse_type("bigint"), Ok(DataType::Int64));
+        assert_eq!(
+            parse_type("Decimal128(38, 6)"),
+            Ok(DataType::Decimal128(38, 6))
+        );
+        assert_eq!(
+            parse_type("numeric(10, 2)"),
+            Ok(DataType::Decimal128(10, 2))
+        );
+        assert_eq!(parse_type("decimal"), Ok(DataType::Decimal128(20, 10)));
+        assert_eq!(
+            parse_type("timestamp"),
+            Ok(DataType::Timestamp(TimeUnit::Millisecond, None))
+        );
+        assert_eq!(
+            parse_type("Timestamp(Microsecond, None)"),
+            Ok(DataType::Timestamp(TimeUnit::Microsecond, None))
+        );
+        assert_eq!(
+            parse_type("Timestamp(Nanosecond, Some(\"+08:00\"))"),
+            Ok(DataType::Timestamp(
+                TimeUnit::Nanosecond,
+                Some("+08:00".into())
+            ))
+        );
+        assert_eq!(parse_type("Date32"), Ok(DataType::Date32));
+    }
+
+    #[test]
+    fn test_parse_nested_type() {
+        assert_eq!(
+            parse_type("List<Int32>"),
+            Ok(DataType::List(Arc::new(Field::new(
+                "item",
+                DataType::Int32,
+                true
+            ))))
+        );
+        assert_eq!(
+            parse_type("Struct<a: Utf8, b: List<Float64>, c: Timestamp(Second, Some(\"+01:00\"))>"),
+            Ok(DataType::Struct(Fields::from(vec![
+                Field::new("a", DataType::Utf8, true),
+                Field::new(
+                    "b",
+                    DataType::List(Arc::new(Field::new("item", DataType::Float64, true))),
+                    true
+                ),
+                Field::new(
+                    "c",
+                    DataType::Timestamp(TimeUnit::Second, Some("+01:00".into())),
+                    true
+                ),
+            ])))
+        );
+        assert_eq!(
+            parse_type("FixedSizeList<Float32, 3>"),
+            Ok(DataType::FixedSizeList(
+                Arc::new(Field::new("item", DataType::Float32, true)),
+                3
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_bad_type() {
+        assert!(parse_type("Strng").is_err());
+        assert!(parse_type("Decimal128").is_err());
+        assert!(parse_type("Decimal128(39, 2)").is_err());
+        assert!(parse_type("Decimal128(5, 6)").is_err());
+        assert!(parse_type("List<Int32").is_err());
+        assert!(parse_type("List<Int32, Int64>").is_err());
+        assert!(parse_type("Int64 extra").is_err());
+    }
+}
@@ -17,18 +17,39 @@
 
 // from https://raw.githubusercontent.com/apache/arrow-rs/master/parquet/src/bin/parquet-concat.rs
 
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashSet};
 use std::fs::File;
+use std::path::Path;
 use std::sync::Arc;
 
+use arrow::array::timezone::Tz;
+use arrow::array::{Array, AsArray, UInt32Array};
+use arrow::compute::{cast, take_record_batch};
+use arrow::datatypes::{DataType, Int64Type, SchemaRef, TimeUnit};
+use arrow::util::display::array_value_to_string;
+use arrow_array::{ArrayRef, RecordBatch};
+use chrono::{DateTime, Offset, TimeZone};
 use clap::Parser;
 use eyre::Report;
-use parquet::column::writer::ColumnCloseResult;
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use parquet::arrow::arrow_reader::{ArrowReaderMetadata, ParquetRecordBatchReaderBuilder};
+use parquet::arrow::ProjectionMask;
 use parquet::errors::ParquetError;
-use parquet::file::properties::WriterProperties;
-use parquet::file::writer::SerializedFileWriter;
+use parquet::file::metadata::ParquetMetaData;
+use parquet::file::properties::{WriterProperties, WriterPropertiesPtr};
+use parquet::format::KeyValue;
+use parquet::schema::types::TypePtr;
+use serde_json::json;
+
+use crate::cmd::output::{NameTemplate, Output, Outputs};
+use crate::cmd::stats::{array_values, column_index, min_max, stored_time_unit, StatValue};
+use crate::cmd::utils::*;
+use crate::cmd::writer::WriterArgs;
 
 #[derive(Debug, Parser)]
-/// split one parquet to multiple ones by row groups
+/// split one parquet to multiple ones by row groups or rows
 pub struct Args {
     /// Path to output
     #[clap(short, long)]
@@ -38,59 +59,772 @@ pub struct Args {
     /// Number of row groups in each output
     groups: u32,
 
+    #[clap(long)]
+    /// Number of rows in each output, decodes and re-encodes the input
+    rows_per_file: Option<usize>,
+
+    #[clap(long)]
+    /// Number of rows in each row group of the outputs, decodes and re-encodes the input.
+    /// Without --rows-per-file each output gets --groups such row groups
+    row_group_rows: Option<usize>,
+
+    #[clap(long, value_parser = parse_size)]
+    /// Max size of each output, e.g. 256MB. Replaces --groups when copying row groups
+    max_file_bytes: Option<usize>,
+
+    #[clap(long, value_delimiter = ',', conflicts_with_all = ["time_column", "bucket_by"])]
+    /// Columns to partition by, writes <output>/<col>=<value>/part-NNNN.parquet
+    partition_by: Vec<String>,
+
+    #[clap(long, default_value_t = false)]
+    /// Keep the partition columns in the data files
+    keep_partition_columns: bool,
+
+    #[clap(long, default_value_t = 64)]
+    /// Max number of partition or time bucket files open at the same time, the
+    /// least recently used one is closed and a new file is started for it later
+    max_open_files: usize,
+
+    #[clap(
+        long,
+        conflicts_with_all = ["bucket_by", "rows_per_file", "row_group_rows", "max_file_bytes"]
+    )]
+    /// Timestamp column to split on, writes <output>_<bucket start>.parquet
+    time_column: Option<String>,
+
+    #[clap(long, default_value = "1d", value_parser = parse_duration)]
+    /// Length of the time buckets, e.g. 15m, 1h or 1d
+    bucket: i64,
+
+    #[clap(long)]
+    /// Time zone the buckets are aligned to, default is the one of the column or UTC
+    tz: Option<String>,
+
+    #[clap(long, value_delimiter = ',', conflicts_with_all = ["rows_per_file", "max_file_bytes"])]
+    /// Columns to hash rows on, writes --buckets outputs with murmur3(key) % N
+    bucket_by: Vec<String>,
+
+    #[clap(long, default_value_t = 8, value_parser = clap::value_parser!(u32).range(1..=i32::MAX as i64))]
+    /// Number of hash buckets
+    buckets: u32,
+
+    #[clap(long)]
+    /// Output names relative to --output, e.g. {date}/{device}_{idx:05}.parquet.
+    /// Placeholders are {idx}, {uuid}, {min} and {max} of --name-column, the
+    /// partition columns, {partition} and {bucket}; {name:0N} pads with zeros
+    name_template: Option<String>,
+
+    #[clap(long)]
+    /// Column whose min and max values fill {min} and {max}
+    name_column: Option<String>,
+
+    #[clap(long, default_value_t = false)]
+    /// List the files that would be written with their row counts, without writing them
+    dry_run: bool,
+
+    #[clap(long, default_value_t = false)]
+    /// Replace existing output files
+    force: bool,
+
     /// Path to input files
     input: String,
+
+    #[command(flatten)]
+    writer: WriterArgs,
+}
+
+/// writes record batches to a sequence of outputs, rolling over to the next
+/// one when the current one reaches `max_rows` or `max_bytes`
+struct RollingWriter {
+    values: Vec<(String, String)>,
+    schema: SchemaRef,
+    props: WriterProperties,
+    max_rows: Option<usize>,
+    max_bytes: Option<usize>,
+    current: Option<Output>,
+}
+
+impl RollingWriter {
+    fn write(&mut self, outputs: &mut Outputs, batch: &RecordBatch) -> eyre::Result<()> {
+        let mut offset = 0;
+        while offset < batch.num_rows() {
+            if self.current.is_none() {
+                let output = outputs.open_arrow(&self.values, &self.schema, self.props.clone())?;
+                self.current = Some(output);
+            }
+
+            let output = self.current.as_mut().unwrap();
+            let left = batch.num_rows() - offset;
+            let len = match self.max_rows {
+                Some(max) => left.min(max - output.rows()),
+                None => left,
+            };
+            output.write(&batch.slice(offset, len))?;
+            offset += len;
+
+            // buffered rows are counted by their in-memory size, the written
+            // file is usually smaller once they are encoded and compressed
+            if self.max_rows.map_or(false, |max| output.rows() >= max)
+                || self.max_bytes.map_or(false, |max| output.size() >= max)
+            {
+                self.roll(outputs)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn roll(&mut self, outputs: &mut Outputs) -> eyre::Result<()> {
+        if let Some(output) = self.current.take() {
+            outputs.close(output)?;
+        }
+        Ok(())
+    }
+
+    fn is_open(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn close(mut self, outputs: &mut Outputs) -> eyre::Result<()> {
+        self.roll(outputs)
+    }
+}
+
+/// outputs named by `--name-template` under `--output`, or by the `default`
+/// template of the split mode where `{output}` is `--output`. `values` are the
+/// placeholders the split mode fills besides the built in ones
+fn outputs(args: &Args, default: &str, values: &[String]) -> eyre::Result<Outputs> {
+    let template = match &args.name_template {
+        Some(t) => Path::new(&args.output)
+            .join(t)
+            .to_string_lossy()
+            .to_string(),
+        None => default.replace("{output}", &args.output),
+    };
+    Outputs::new(
+        NameTemplate::new(&template)?,
+        values,
+        Path::new(&args.output),
+        args.name_column.clone(),
+        args.dry_run,
+        args.force,
+        args.writer.jobs,
+    )
+}
+
+/// leaf index of `--name-column` when copied row groups must be decoded for {min}/{max}
+fn name_leaf(outputs: &Outputs, metadata: &ParquetMetaData) -> eyre::Result<Option<usize>> {
+    match outputs.name_column() {
+        Some(column) if outputs.needs_range() => {
+            let leaf = column_index(metadata.file_metadata().schema_descr(), column)
+                .ok_or_else(|| eyre::eyre!("{column} is not a leaf column"))?;
+            Ok(Some(leaf))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// decode the name column of copied row group `idx` into the min/max of `output`
+fn track_row_group(
+    output: &mut Output,
+    reader: &File,
+    metadata: &ArrowReaderMetadata,
+    idx: usize,
+    name_leaf: Option<usize>,
+) -> eyre::Result<()> {
+    let Some(leaf) = name_leaf else {
+        return Ok(());
+    };
+    let mask = ProjectionMask::leaves(metadata.metadata().file_metadata().schema_descr(), [leaf]);
+    let batches =
+        ParquetRecordBatchReaderBuilder::new_with_metadata(reader.try_clone()?, metadata.clone())
+            .with_row_groups(vec![idx])
+            .with_projection(mask)
+            .build()?;
+    for batch in batches {
+        output.track(batch?.column(0))?;
+    }
+    Ok(())
 }
 
-pub fn split_main(args: Args) -> eyre::Result<()> {
+pub fn split_main(mut args: Args) -> eyre::Result<()> {
     if args.input.is_empty() {
         return Err(Report::from(ParquetError::General(
             "Must provide one input file".into(),
         )));
     }
 
-    let reader = File::open(args.input).unwrap();
+    let reader = File::open(&args.input).unwrap();
     let metadata = parquet::file::footer::parse_metadata(&reader).unwrap();
 
-    let props = Arc::new(WriterProperties::builder().build());
+    if args.time_column.is_some() {
+        args.writer.reencode = true;
+        return split_time(&args, reader, &metadata);
+    }
+    if !args.bucket_by.is_empty() {
+        args.writer.reencode = true;
+        return split_hash(&args, reader, &metadata);
+    }
+    if !args.partition_by.is_empty() {
+        args.writer.reencode = true;
+        return split_partitions(&args, reader, &metadata);
+    }
+    if args.rows_per_file.is_some()
+        || args.row_group_rows.is_some()
+        || (args.max_file_bytes.is_some() && args.writer.reencode)
+    {
+        args.writer.reencode = true;
+        return split_rows(&args, reader, &metadata);
+    }
+    split_groups(&args, reader, &metadata)
+}
+
+/// split at row group boundaries, `--groups` row groups or up to
+/// `--max-file-bytes` compressed bytes per output
+fn split_groups(args: &Args, reader: File, metadata: &ParquetMetaData) -> eyre::Result<()> {
+    let arrow_metadata = ArrowReaderMetadata::load(&reader, Default::default())?;
+
+    let props = Arc::new(args.writer.properties(&[metadata]));
     let schema = metadata.file_metadata().schema_descr().root_schema_ptr();
 
-    let mut output_idx = 0;
-    let mut left = metadata.row_groups().len() as u32;
-    let mut rg_iter = metadata.row_groups().into_iter();
-
-    while left > 0 {
-        let output = format!("{}_{:04}.parquet", args.output, output_idx);
-        output_idx += 1;
-
-        let output = File::create(output)?;
-        let mut writer = SerializedFileWriter::new(output, schema.clone(), props.clone())?;
-
-        for _ in 0..args.groups {
-            match rg_iter.next() {
-                Some(rg) => {
-                    left -= 1;
-                    let mut rg_out = writer.next_row_group()?;
-                    for column in rg.columns() {
-                        let result = ColumnCloseResult {
-                            bytes_written: column.compressed_size() as _,
-                            rows_written: rg.num_rows() as _,
-                            metadata: column.clone(),
-                            bloom_filter: None,
-                            column_index: None,
-                            offset_index: None,
-                        };
-                        rg_out.append_column(&reader, result)?;
-                    }
-                    rg_out.close()?;
+    let mut outputs = outputs(args, "{output}_{idx:04}.parquet", &[])?;
+    let name_leaf = name_leaf(&outputs, metadata)?;
+    // the current output, with the compressed bytes of its input row groups
+    let mut current: Option<(Output, usize)> = None;
+
+    for (idx, rg) in metadata.row_groups().iter().enumerate() {
+        let full = match (&current, args.max_file_bytes) {
+            (Some((_, bytes)), Some(max)) => bytes + rg.compressed_size() as usize > max,
+            (Some((output, _)), None) => output.row_groups() >= args.groups as usize,
+            (None, _) => false,
+        };
+        if full {
+            outputs.close(current.take().unwrap().0)?;
+        }
+        if current.is_none() {
+            let output =
+                outputs.open_chunk(&[], arrow_metadata.schema(), schema.clone(), props.clone())?;
+            current = Some((output, 0));
+        }
+
+        let (output, bytes) = current.as_mut().unwrap();
+        if args.writer.reencode {
+            let batch = read_row_group(&reader, &arrow_metadata, idx)?;
+            output.encode_row_group(&props, &batch)?;
+        } else {
+            output.copy_row_group(&reader, rg)?;
+            track_row_group(output, &reader, &arrow_metadata, idx, name_leaf)?;
+        }
+        *bytes += rg.compressed_size() as usize;
+    }
+
+    if let Some((output, _)) = current {
+        outputs.close(output)?;
+    }
+    outputs.finish()
+}
+
+/// split by row counts, streaming record batches through `ArrowWriter`
+fn split_rows(args: &Args, reader: File, metadata: &ParquetMetaData) -> eyre::Result<()> {
+    if args.rows_per_file == Some(0) || args.row_group_rows == Some(0) {
+        return Err(eyre::eyre!("row counts must be bigger than 0"));
+    }
+
+    let builder = ParquetRecordBatchReaderBuilder::try_new(reader)?;
+    let mut outputs = outputs(args, "{output}_{idx:04}.parquet", &[])?;
+    let mut writer = rolling_writer(args, metadata, vec![], builder.schema().clone());
+
+    for batch in builder.build()? {
+        writer.write(&mut outputs, &batch?)?;
+    }
+    writer.close(&mut outputs)?;
+    outputs.finish()
+}
+
+/// a rolling writer honoring the row and size limits of `args`
+fn rolling_writer(
+    args: &Args,
+    metadata: &ParquetMetaData,
+    values: Vec<(String, String)>,
+    schema: SchemaRef,
+) -> RollingWriter {
+    let max_rows = args
+        .rows_per_file
+        .or(args.row_group_rows.map(|r| r * args.groups as usize));
+
+    let mut props = args.writer.builder(&[metadata]);
+    if let Some(rows) = args.row_group_rows {
+        props = props.set_max_row_group_size(rows);
+    }
+
+    RollingWriter {
+        values,
+        schema,
+        props: props.build(),
+        max_rows,
+        max_bytes: args.max_file_bytes,
+        current: None,
+    }
+}
+
+struct Partition {
+    writer: RollingWriter,
+    rows: usize,
+    last_used: usize,
+}
+
+/// route rows into hive style partition directories
+fn split_partitions(args: &Args, reader: File, metadata: &ParquetMetaData) -> eyre::Result<()> {
+    if args.max_open_files == 0 {
+        return Err(eyre::eyre!("--max-open-files must be bigger than 0"));
+    }
+
+    let builder = ParquetRecordBatchReaderBuilder::try_new(reader)?;
+    let schema = builder.schema().clone();
+
+    let partition_idx = args
+        .partition_by
+        .iter()
+        .map(|c| schema.index_of(c))
+        .collect::<Result<Vec<_>, _>>()?;
+    let data_idx = (0..schema.fields().len())
+        .filter(|i| args.keep_partition_columns || !partition_idx.contains(i))
+        .collect::<Vec<_>>();
+    let data_schema = Arc::new(schema.project(&data_idx)?);
+
+    let mut names = args.partition_by.clone();
+    names.push("partition".to_owned());
+    let mut outputs = outputs(args, "{output}/{partition}/part-{idx:04}.parquet", &names)?;
+    let mut partitions: BTreeMap<String, Partition> = BTreeMap::new();
+    // open partitions by the tick they were last written at, the first one
+    // is the least recently used
+    let mut open: BTreeMap<usize, String> = BTreeMap::new();
+    let mut tick = 0;
+
+    for batch in builder.build()? {
+        let batch = batch?;
+
+        let mut groups: BTreeMap<Vec<(String, String)>, Vec<u32>> = BTreeMap::new();
+        for row in 0..batch.num_rows() {
+            let values = partition_idx
+                .iter()
+                .map(|&i| {
+                    Ok((
+                        schema.field(i).name().clone(),
+                        partition_value(batch.column(i), row)?,
+                    ))
+                })
+                .collect::<eyre::Result<Vec<_>>>()?;
+            groups.entry(values).or_default().push(row as u32);
+        }
+
+        for (mut values, rows) in groups {
+            let path = values
+                .iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect::<Vec<_>>()
+                .join("/");
+
+            tick += 1;
+            let needs_open = partitions.get(&path).map_or(true, |p| !p.writer.is_open());
+            if needs_open && open.len() >= args.max_open_files {
+                // roll the least recently used partition
+                if let Some((_, lru)) = open.pop_first() {
+                    partitions
+                        .get_mut(&lru)
+                        .unwrap()
+                        .writer
+                        .roll(&mut outputs)?;
                 }
-                None => {
-                    break;
+            }
+
+            let partition = match partitions.entry(path.clone()) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => {
+                    values.push(("partition".to_owned(), e.key().clone()));
+                    let writer = rolling_writer(args, metadata, values, data_schema.clone());
+                    e.insert(Partition {
+                        writer,
+                        rows: 0,
+                        last_used: 0,
+                    })
                 }
+            };
+
+            let part = take_record_batch(&batch, &UInt32Array::from(rows))?.project(&data_idx)?;
+            partition.writer.write(&mut outputs, &part)?;
+            partition.rows += part.num_rows();
+            open.remove(&partition.last_used);
+            partition.last_used = tick;
+            // the writer rolls itself at the row and size limits
+            if partition.writer.is_open() {
+                open.insert(tick, path);
+            }
+        }
+    }
+
+    for partition in partitions.values_mut() {
+        partition.writer.roll(&mut outputs)?;
+    }
+    outputs.finish()?;
+
+    let mut files: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for w in outputs.written() {
+        if let Some((_, v)) = w.values.iter().find(|(k, _)| k == "partition") {
+            files.entry(v).or_default().push(w.path.clone());
+        }
+    }
+
+    let mut summary = vec![];
+    for (path, partition) in &partitions {
+        let files = files.remove(path.as_str()).unwrap_or_default();
+        let rows = partition.rows;
+
+        println!("{path}: {rows} rows in {} files", files.len());
+        summary.push(json!({
+            "partition": path,
+            "rows": rows,
+            "files": files,
+        }));
+    }
+
+    if !args.dry_run {
+        std::fs::create_dir_all(&args.output)?;
+        let summary_path = Path::new(&args.output).join("_summary.json");
+        serde_json::to_writer_pretty(File::create(summary_path)?, &summary)?;
+    }
+    Ok(())
+}
+
+/// hive style partition value of `array` at `row`
+fn partition_value(array: &ArrayRef, row: usize) -> eyre::Result<String> {
+    if array.is_null(row) {
+        return Ok("__HIVE_DEFAULT_PARTITION__".to_owned());
+    }
+    let value = array_value_to_string(array, row)?;
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c.is_control() || "\"#%'*/:=?\\{}[]^".contains(c) {
+            for b in c.to_string().bytes() {
+                escaped.push_str(&format!("%{b:02X}"));
+            }
+        } else {
+            escaped.push(c);
+        }
+    }
+    Ok(escaped)
+}
+
+/// time buckets of a timestamp column in a time zone
+#[derive(Clone)]
+struct Buckets {
+    tz: Tz,
+    /// ticks of the column unit per second
+    per_second: i64,
+    /// bucket length in seconds
+    length: i64,
+}
+
+/// ticks of `unit` per second
+fn per_second(unit: &TimeUnit) -> i64 {
+    match unit {
+        TimeUnit::Second => 1,
+        TimeUnit::Millisecond => 1_000,
+        TimeUnit::Microsecond => 1_000_000,
+        TimeUnit::Nanosecond => 1_000_000_000,
+    }
+}
+
+impl Buckets {
+    /// bucket of the timestamp `v`, in column units since the epoch
+    fn bucket(&self, v: i64) -> i64 {
+        let seconds = v.div_euclid(self.per_second);
+        let offset = DateTime::from_timestamp(seconds, 0)
+            .map(|t| {
+                self.tz
+                    .offset_from_utc_datetime(&t.naive_utc())
+                    .fix()
+                    .local_minus_utc() as i64
+            })
+            .unwrap_or(0);
+        (seconds + offset).div_euclid(self.length)
+    }
+
+    /// local start time of `bucket`, used in output names
+    fn label(&self, bucket: i64) -> String {
+        let format = match self.length {
+            l if l % 86400 == 0 => "%Y-%m-%d",
+            l if l % 3600 == 0 => "%Y-%m-%dT%H",
+            l if l % 60 == 0 => "%Y-%m-%dT%H%M",
+            _ => "%Y-%m-%dT%H%M%S",
+        };
+        DateTime::from_timestamp(bucket * self.length, 0)
+            .map(|t| t.naive_utc().format(format).to_string())
+            .unwrap_or_else(|| bucket.to_string())
+    }
+}
+
+/// one output per time bucket, None is the bucket of null timestamps. At most
+/// `max_open` outputs are open, the least recently used one is closed first
+struct BucketWriters<'a> {
+    outputs: Outputs,
+    buckets: &'a Buckets,
+    arrow_schema: SchemaRef,
+    schema: TypePtr,
+    props: WriterPropertiesPtr,
+    max_open: usize,
+    /// open outputs with the tick they were last used at
+    writers: BTreeMap<Option<i64>, (Output, usize)>,
+    /// buckets whose outputs were closed before the end of the input
+    closed: HashSet<Option<i64>>,
+    tick: usize,
+}
+
+impl BucketWriters<'_> {
+    fn label(&self, bucket: Option<i64>) -> String {
+        match bucket {
+            Some(b) => self.buckets.label(b),
+            None => "null".to_owned(),
+        }
+    }
+
+    fn get(&mut self, bucket: Option<i64>) -> eyre::Result<&mut Output> {
+        self.tick += 1;
+        if !self.writers.contains_key(&bucket) {
+            if self.closed.contains(&bucket) && !self.outputs.unique_names() {
+                return Err(eyre::eyre!(
+                    "bucket {} continues after its file was closed, sort the input on \
+                     the time column, raise --max-open-files or add {{idx}} to --name-template",
+                    self.label(bucket)
+                ));
+            }
+            if self.writers.len() >= self.max_open {
+                let lru = self
+                    .writers
+                    .iter()
+                    .min_by_key(|(_, (_, last_used))| *last_used)
+                    .map(|(b, _)| *b)
+                    .unwrap();
+                let (output, _) = self.writers.remove(&lru).unwrap();
+                self.outputs.close(output)?;
+                self.closed.insert(lru);
+            }
+            let label = self.label(bucket);
+            let output = self.outputs.open_chunk(
+                &[("bucket".to_owned(), label)],
+                &self.arrow_schema,
+                self.schema.clone(),
+                self.props.clone(),
+            )?;
+            self.writers.insert(bucket, (output, 0));
+        }
+
+        let (output, last_used) = self.writers.get_mut(&bucket).unwrap();
+        *last_used = self.tick;
+        Ok(output)
+    }
+}
+
+/// split on time buckets of `--time-column`, row groups inside one bucket
+/// are copied, the ones crossing bucket boundaries are decoded and cut
+fn split_time(args: &Args, reader: File, metadata: &ParquetMetaData) -> eyre::Result<()> {
+    if args.max_open_files == 0 {
+        return Err(eyre::eyre!("--max-open-files must be bigger than 0"));
+    }
+
+    let column = args.time_column.as_ref().unwrap();
+    let arrow_metadata = ArrowReaderMetadata::load(&reader, Default::default())?;
+    let schema = arrow_metadata.schema().clone();
+
+    let arrow_idx = schema.index_of(column)?;
+    let leaf_idx = column_index(metadata.file_metadata().schema_descr(), column)
+        .ok_or_else(|| eyre::eyre!("{column} is not a leaf column"))?;
+
+    let (unit, column_tz) = match schema.field(arrow_idx).data_type() {
+        DataType::Timestamp(unit, tz) => (unit.clone(), tz.clone()),
+        v => return Err(eyre::eyre!("{column} is {v}, not a timestamp")),
+    };
+    let tz = args
+        .tz
+        .as_deref()
+        .or(column_tz.as_deref())
+        .unwrap_or("+00:00")
+        .parse::<Tz>()?;
+    let buckets = Buckets {
+        tz,
+        per_second: per_second(&unit),
+        length: args.bucket,
+    };
+    // the statistics are in the unit of the file, e.g. milliseconds for
+    // seconds written by other writers
+    let stored_unit = stored_time_unit(
+        &metadata.file_metadata().schema_descr().column(leaf_idx),
+        &unit,
+    );
+    let stat_buckets = Buckets {
+        per_second: per_second(&stored_unit),
+        ..buckets.clone()
+    };
+
+    let props = Arc::new(args.writer.properties(&[metadata]));
+
+    let outputs = outputs(args, "{output}_{bucket}.parquet", &["bucket".to_owned()])?;
+    let name_leaf = name_leaf(&outputs, metadata)?;
+    let mut writers = BucketWriters {
+        outputs,
+        buckets: &buckets,
+        arrow_schema: schema.clone(),
+        schema: metadata.file_metadata().schema_descr().root_schema_ptr(),
+        props: props.clone(),
+        max_open: args.max_open_files,
+        writers: BTreeMap::new(),
+        closed: HashSet::new(),
+        tick: 0,
+    };
+
+    let (mut copied, mut cut) = (0, 0);
+    for (idx, rg) in metadata.row_groups().iter().enumerate() {
+        let chunk = rg.column(leaf_idx);
+        let no_nulls = chunk.statistics().map_or(false, |s| s.null_count() == 0);
+        if let (true, Some((StatValue::Int(min), StatValue::Int(max)))) = (no_nulls, min_max(chunk))
+        {
+            let bucket = stat_buckets.bucket(min);
+            if bucket == stat_buckets.bucket(max) {
+                let output = writers.get(Some(bucket))?;
+                output.copy_row_group(&reader, rg)?;
+                track_row_group(output, &reader, &arrow_metadata, idx, name_leaf)?;
+                copied += 1;
+                continue;
+            }
+        }
+
+        let batch = read_row_group(&reader, &arrow_metadata, idx)?;
+        let values = cast(batch.column(arrow_idx), &DataType::Int64)?;
+        let values = values.as_primitive::<Int64Type>();
+
+        let mut pieces: BTreeMap<Option<i64>, Vec<u32>> = BTreeMap::new();
+        for (row, v) in values.iter().enumerate() {
+            pieces
+                .entry(v.map(|v| buckets.bucket(v)))
+                .or_default()
+                .push(row as u32);
+        }
+        for (bucket, rows) in pieces {
+            let piece = take_record_batch(&batch, &UInt32Array::from(rows))?;
+            writers.get(bucket)?.encode_row_group(&props, &piece)?;
+        }
+        cut += 1;
+    }
+
+    for (_, (output, _)) in std::mem::take(&mut writers.writers) {
+        writers.outputs.close(output)?;
+    }
+    info!("copied {copied} row groups, cut {cut} row groups");
+    writers.outputs.finish()
+}
+
+/// seed of the bucket hash, the hash of each key column seeds the next one
+const BUCKET_HASH_SEED: u32 = 42;
+
+/// bytes hashed for each type of value, see [`hash_column`]. This is not the
+/// encoding of Spark's bucketing, bucket ids differ from the ones Spark computes
+const BUCKET_HASH_ENCODING: &str =
+    "int:i64le,uint:u64le,decimal:i128le,float:f64le,bool:u8,binary:bytes,other:display,null:skip";
+
+/// split into `--buckets` files on the hash of `--bucket-by`
+fn split_hash(args: &Args, reader: File, metadata: &ParquetMetaData) -> eyre::Result<()> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(reader)?;
+    let schema = builder.schema().clone();
+    let key_idx = args
+        .bucket_by
+        .iter()
+        .map(|c| schema.index_of(c))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut props = args.writer.builder(&[metadata]);
+    if let Some(rows) = args.row_group_rows {
+        props = props.set_max_row_group_size(rows);
+    }
+    let props = props.build();
+
+    // the bucket spec lets readers know how rows were distributed
+    let spec = [
+        ("pp.bucket.columns", args.bucket_by.join(",")),
+        ("pp.bucket.count", args.buckets.to_string()),
+        ("pp.bucket.hash", "murmur3_32".to_owned()),
+        ("pp.bucket.seed", BUCKET_HASH_SEED.to_string()),
+        ("pp.bucket.encoding", BUCKET_HASH_ENCODING.to_owned()),
+    ];
+
+    let mut outputs = outputs(args, "{output}_{bucket:04}.parquet", &["bucket".to_owned()])?;
+    let mut writers = (0..args.buckets)
+        .map(|i| {
+            let mut output = outputs.open_arrow(
+                &[("bucket".to_owned(), i.to_string())],
+                &schema,
+                props.clone(),
+            )?;
+            for (key, value) in &spec {
+                output.append_key_value_metadata(KeyValue::new(key.to_string(), value.clone()))?;
             }
+            output.append_key_value_metadata(KeyValue::new(
+                "pp.bucket.id".to_owned(),
+                i.to_string(),
+            ))?;
+            Ok(output)
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    for batch in builder.build()? {
+        let batch = batch?;
+        let mut hashes = vec![BUCKET_HASH_SEED; batch.num_rows()];
+        for &i in &key_idx {
+            hash_column(batch.column(i), &mut hashes)?;
+        }
+
+        let mut rows = vec![vec![]; args.buckets as usize];
+        for (row, h) in hashes.into_iter().enumerate() {
+            rows[(h as i32).rem_euclid(args.buckets as i32) as usize].push(row as u32);
         }
-        writer.close()?;
+        for (bucket, rows) in rows.into_iter().enumerate() {
+            if rows.is_empty() {
+                continue;
+            }
+            writers[bucket].write(&take_record_batch(&batch, &UInt32Array::from(rows))?)?;
+        }
+    }
+
+    for (bucket, output) in writers.into_iter().enumerate() {
+        println!("bucket {bucket}: {} rows", output.rows());
+        outputs.close(output)?;
+    }
+    outputs.finish()
+}
+
+/// chain the hash of each value of `array` into `hashes`, nulls keep the hash.
+/// Integers, dates and timestamps are hashed as 8 little endian bytes, decimals
+/// as their 16 byte unscaled value, floats as the bits of an f64, strings and
+/// binaries as their bytes, other types as their display value
+fn hash_column(array: &ArrayRef, hashes: &mut [u32]) -> eyre::Result<()> {
+    if let Some(values) = array_values(array) {
+        for (h, v) in hashes.iter_mut().zip(values) {
+            *h = match v {
+                Some(StatValue::Int(v)) => murmur3_32(&v.to_le_bytes(), *h),
+                Some(StatValue::UInt(v)) => murmur3_32(&v.to_le_bytes(), *h),
+                Some(StatValue::Decimal(v)) => murmur3_32(&v.to_le_bytes(), *h),
+                Some(StatValue::Float(v)) => murmur3_32(&v.to_bits().to_le_bytes(), *h),
+                Some(StatValue::Bytes(v)) => murmur3_32(&v, *h),
+                Some(StatValue::Bool(v)) => murmur3_32(&[v as u8], *h),
+                None => *h,
+            };
+        }
+        return Ok(());
     }
 
+    // other types hash their display value
+    for (row, h) in hashes.iter_mut().enumerate() {
+        if array.is_valid(row) {
+            *h = murmur3_32(array_value_to_string(array, row)?.as_bytes(), *h);
+        }
+    }
     Ok(())
 }
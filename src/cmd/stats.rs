@@ -0,0 +1,268 @@
+use std::cmp::Ordering;
+
+use arrow::array::AsArray;
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Decimal128Type, Float64Type, Int64Type, TimeUnit, UInt64Type};
+use arrow_array::ArrayRef;
+use parquet::basic::{
+    ConvertedType, LogicalType, TimeUnit as ParquetTimeUnit, Type as PhysicalType,
+};
+use parquet::file::metadata::{ColumnChunkMetaData, RowGroupMetaData};
+use parquet::file::statistics::Statistics;
+use parquet::schema::types::{ColumnDescriptor, SchemaDescriptor};
+
+/// min or max value of a column chunk, ordered by its logical type
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatValue {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    /// unscaled value of a decimal
+    Decimal(i128),
+    Float(f64),
+    /// strings and binaries, compared as unsigned bytes
+    Bytes(Vec<u8>),
+}
+
+impl PartialOrd for StatValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (StatValue::Bool(a), StatValue::Bool(b)) => a.partial_cmp(b),
+            (StatValue::Int(a), StatValue::Int(b)) => a.partial_cmp(b),
+            (StatValue::UInt(a), StatValue::UInt(b)) => a.partial_cmp(b),
+            (StatValue::Decimal(a), StatValue::Decimal(b)) => a.partial_cmp(b),
+            (StatValue::Float(a), StatValue::Float(b)) => a.partial_cmp(b),
+            (StatValue::Bytes(a), StatValue::Bytes(b)) => a.partial_cmp(b),
+            _ => None,
+        }
+    }
+}
+
+/// index of the leaf column `name`, nested columns use dotted paths
+pub fn column_index(schema: &SchemaDescriptor, name: &str) -> Option<usize> {
+    schema
+        .columns()
+        .iter()
+        .position(|c| c.path().string() == name)
+}
+
+/// min and max of a column chunk, None if the chunk has no usable statistics.
+/// Unsigned integers and decimals are ordered by value, chunks of fixed length
+/// types without a byte order like intervals or float16 have none
+pub fn min_max(column: &ColumnChunkMetaData) -> Option<(StatValue, StatValue)> {
+    let stats = column.statistics()?;
+    if !stats.has_min_max_set() {
+        return None;
+    }
+
+    let descr = column.column_descr();
+    let converted = descr.converted_type();
+    let decimal = converted == ConvertedType::DECIMAL;
+    let unsigned = matches!(
+        converted,
+        ConvertedType::UINT_8
+            | ConvertedType::UINT_16
+            | ConvertedType::UINT_32
+            | ConvertedType::UINT_64
+    );
+
+    let v = match stats {
+        Statistics::Boolean(s) => (StatValue::Bool(*s.min()), StatValue::Bool(*s.max())),
+        Statistics::Int32(s) if decimal => (
+            StatValue::Decimal(*s.min() as i128),
+            StatValue::Decimal(*s.max() as i128),
+        ),
+        Statistics::Int32(s) if unsigned => (
+            StatValue::UInt(*s.min() as u32 as u64),
+            StatValue::UInt(*s.max() as u32 as u64),
+        ),
+        Statistics::Int32(s) => (
+            StatValue::Int(*s.min() as i64),
+            StatValue::Int(*s.max() as i64),
+        ),
+        Statistics::Int64(s) if decimal => (
+            StatValue::Decimal(*s.min() as i128),
+            StatValue::Decimal(*s.max() as i128),
+        ),
+        Statistics::Int64(s) if unsigned => (
+            StatValue::UInt(*s.min() as u64),
+            StatValue::UInt(*s.max() as u64),
+        ),
+        Statistics::Int64(s) => (StatValue::Int(*s.min()), StatValue::Int(*s.max())),
+        Statistics::Float(s) => (
+            StatValue::Float(*s.min() as f64),
+            StatValue::Float(*s.max() as f64),
+        ),
+        Statistics::Double(s) => (StatValue::Float(*s.min()), StatValue::Float(*s.max())),
+        Statistics::ByteArray(s) if decimal => (
+            StatValue::Decimal(be_i128(s.min().data())?),
+            StatValue::Decimal(be_i128(s.max().data())?),
+        ),
+        Statistics::ByteArray(s) => (
+            StatValue::Bytes(s.min().data().to_vec()),
+            StatValue::Bytes(s.max().data().to_vec()),
+        ),
+        Statistics::FixedLenByteArray(s) if decimal => (
+            StatValue::Decimal(be_i128(s.min().data())?),
+            StatValue::Decimal(be_i128(s.max().data())?),
+        ),
+        Statistics::FixedLenByteArray(s) => {
+            let plain = converted == ConvertedType::NONE
+                && matches!(descr.logical_type(), None | Some(LogicalType::Uuid));
+            if !plain {
+                return None;
+            }
+            (
+                StatValue::Bytes(s.min().data().to_vec()),
+                StatValue::Bytes(s.max().data().to_vec()),
+            )
+        }
+        Statistics::Int96(_) => return None,
+    };
+    Some(v)
+}
+
+/// value of a signed big-endian two's complement integer of at most 16 bytes
+fn be_i128(bytes: &[u8]) -> Option<i128> {
+    if bytes.is_empty() || bytes.len() > 16 {
+        return None;
+    }
+    let fill = if bytes[0] & 0x80 != 0 { 0xff } else { 0 };
+    let mut buf = [fill; 16];
+    buf[16 - bytes.len()..].copy_from_slice(bytes);
+    Some(i128::from_be_bytes(buf))
+}
+
+/// min and max of column `idx` over all `row_groups`, None if any of them lacks statistics
+pub fn row_groups_min_max<'a, I>(row_groups: I, idx: usize) -> Option<(StatValue, StatValue)>
+where
+    I: IntoIterator<Item = &'a RowGroupMetaData>,
+{
+    let mut result: Option<(StatValue, StatValue)> = None;
+    for rg in row_groups {
+        let (min, max) = min_max(rg.column(idx))?;
+        result = Some(match result {
+            None => (min, max),
+            Some((cur_min, cur_max)) => (
+                if min < cur_min { min } else { cur_min },
+                if max > cur_max { max } else { cur_max },
+            ),
+        });
+    }
+    result
+}
+
+/// values of `array` in the units its parquet column `descr` stores, so they
+/// compare with the column statistics. Date64 is kept as Date32 days and
+/// timestamps may be kept in another unit than the arrow one, e.g. seconds as
+/// milliseconds by other writers
+pub fn stat_values(array: &ArrayRef, descr: &ColumnDescriptor) -> Option<Vec<Option<StatValue>>> {
+    let stored = match array.data_type() {
+        DataType::Timestamp(unit, tz) => {
+            let unit = stored_time_unit(descr, unit);
+            cast(array, &DataType::Timestamp(unit, tz.clone())).ok()?
+        }
+        DataType::Date64 if descr.physical_type() == PhysicalType::INT32 => {
+            cast(array, &DataType::Date32).ok()?
+        }
+        _ => array.clone(),
+    };
+    array_values(&stored)
+}
+
+/// unit of the timestamps of column `descr` read as `unit`, columns without
+/// a timestamp type like the ones of arrow's Timestamp(Second) keep it
+pub fn stored_time_unit(descr: &ColumnDescriptor, unit: &TimeUnit) -> TimeUnit {
+    match (descr.logical_type(), descr.converted_type()) {
+        (Some(LogicalType::Timestamp { unit, .. }), _) => match unit {
+            ParquetTimeUnit::MILLIS(_) => TimeUnit::Millisecond,
+            ParquetTimeUnit::MICROS(_) => TimeUnit::Microsecond,
+            ParquetTimeUnit::NANOS(_) => TimeUnit::Nanosecond,
+        },
+        (_, ConvertedType::TIMESTAMP_MILLIS) => TimeUnit::Millisecond,
+        (_, ConvertedType::TIMESTAMP_MICROS) => TimeUnit::Microsecond,
+        _ => unit.clone(),
+    }
+}
+
+/// values of `array` as integers, floats or bytes in its arrow units, see
+/// [`stat_values`] to compare them with statistics. None for other types
+pub fn array_values(array: &ArrayRef) -> Option<Vec<Option<StatValue>>> {
+    let values = match array.data_type() {
+        DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::Date32
+        | DataType::Date64
+        | DataType::Timestamp(_, _) => cast(array, &DataType::Int64)
+            .ok()?
+            .as_primitive::<Int64Type>()
+            .iter()
+            .map(|v| v.map(StatValue::Int))
+            .collect(),
+        DataType::UInt8 | DataType::UInt16 | DataType::UInt32 | DataType::UInt64 => {
+            cast(array, &DataType::UInt64)
+                .ok()?
+                .as_primitive::<UInt64Type>()
+                .iter()
+                .map(|v| v.map(StatValue::UInt))
+                .collect()
+        }
+        DataType::Decimal128(_, _) => array
+            .as_primitive::<Decimal128Type>()
+            .iter()
+            .map(|v| v.map(StatValue::Decimal))
+            .collect(),
+        DataType::Float32 | DataType::Float64 => cast(array, &DataType::Float64)
+            .ok()?
+            .as_primitive::<Float64Type>()
+            .iter()
+            .map(|v| v.map(StatValue::Float))
+            .collect(),
+        DataType::Utf8 | DataType::LargeUtf8 | DataType::Binary | DataType::LargeBinary => {
+            cast(array, &DataType::Binary)
+                .ok()?
+                .as_binary::<i32>()
+                .iter()
+                .map(|v| v.map(|b| StatValue::Bytes(b.to_vec())))
+                .collect()
+        }
+        _ => return None,
+    };
+    Some(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_be_i128() {
+        assert_eq!(be_i128(&[0x01, 0x00]), Some(256));
+        assert_eq!(be_i128(&[0xff, 0xfe]), Some(-2));
+        assert_eq!(be_i128(&[0x80]), Some(-128));
+        assert_eq!(be_i128(&[]), None);
+        assert_eq!(be_i128(&[0; 17]), None);
+        // lexicographic order of the bytes would put -2 after 256
+        assert!(StatValue::Decimal(-2) < StatValue::Decimal(256));
+    }
+
+    #[test]
+    fn test_stored_time_unit() {
+        let schema = parquet::schema::parser::parse_message_type(
+            "message m {
+                required int64 raw;
+                required int64 millis (TIMESTAMP_MILLIS);
+                required int64 micros (TIMESTAMP(MICROS, true));
+            }",
+        )
+        .unwrap();
+        let descr = SchemaDescriptor::new(std::sync::Arc::new(schema));
+        let unit = |idx| stored_time_unit(&descr.column(idx), &TimeUnit::Second);
+        assert_eq!(unit(0), TimeUnit::Second);
+        // other writers store arrow seconds as milliseconds
+        assert_eq!(unit(1), TimeUnit::Millisecond);
+        assert_eq!(unit(2), TimeUnit::Microsecond);
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use datafusion::prelude::SessionContext;
+use eyre::Error;
+#[allow(unused_imports)]
+use log::{debug, info, warn};
+use object_store::aws::AmazonS3Builder;
+use object_store::local::LocalFileSystem;
+use object_store::ObjectStore;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreKind {
+    #[default]
+    S3,
+    /// a local directory standing in for a bucket, e.g. in tests
+    Local,
+}
+
+/// where the credentials of a s3 store are read from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum Credentials {
+    /// names of the environment variables, default are the AWS_* ones
+    Env {
+        access_id: Option<String>,
+        secret_key: Option<String>,
+        session_token: Option<String>,
+    },
+    /// a profile of an AWS style credentials file, default are AWS_PROFILE
+    /// or `default` in AWS_SHARED_CREDENTIALS_FILE or ~/.aws/credentials
+    Profile {
+        name: Option<String>,
+        file: Option<String>,
+    },
+}
+
+/// object store settings, unset s3 values are taken from the AWS_* environment
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StoreConfig {
+    #[serde(default)]
+    pub kind: StoreKind,
+    pub bucket: Option<String>,
+    pub region: Option<String>,
+    pub endpoint: Option<String>,
+    /// inline credentials, prefer `credentials` in configs kept in git
+    pub access_id: Option<String>,
+    pub secret_key: Option<String>,
+    /// `{env: {...}}` or `{profile: {...}}`, serde_yaml wants `!env` tags otherwise
+    #[serde(default, with = "serde_yaml::with::singleton_map")]
+    pub credentials: Option<Credentials>,
+    /// default is true for http:// endpoints
+    pub allow_http: Option<bool>,
+    /// directory of a local store
+    pub root: Option<String>,
+}
+
+/// a store of a source or sink, the name of one in `stores` or an inline config
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum StoreRef {
+    Named(String),
+    Inline(StoreConfig),
+}
+
+/// registers object stores on a session, one per URL scheme and bucket
+pub struct Stores {
+    named: HashMap<String, StoreConfig>,
+    registered: HashMap<String, StoreConfig>,
+}
+
+impl Stores {
+    pub fn new(named: HashMap<String, StoreConfig>) -> Self {
+        Stores {
+            named,
+            registered: HashMap::new(),
+        }
+    }
+
+    /// register the store serving `path` on `ctx`, local paths need none.
+    /// Without `store` the bucket is read with the settings of the environment
+    pub fn register(
+        &mut self,
+        ctx: &SessionContext,
+        path: &str,
+        store: Option<&StoreRef>,
+    ) -> eyre::Result<()> {
+        let url = match Url::parse(path) {
+            Ok(url) if url.scheme() != "file" && url.has_host() => url,
+            _ => {
+                if store.is_some() {
+                    warn!("{path} is a local path, its store is not used");
+                }
+                return Ok(());
+            }
+        };
+        let bucket = url.host_str().unwrap_or_default().to_owned();
+        let key = format!("{}://{}", url.scheme(), bucket);
+
+        let config = match store {
+            None => StoreConfig::default(),
+            Some(StoreRef::Inline(config)) => config.clone(),
+            Some(StoreRef::Named(name)) => self
+                .named
+                .get(name)
+                .cloned()
+                .ok_or_else(|| Error::msg(format!("store {name} not found in stores")))?,
+        };
+        if let Some(b) = &config.bucket {
+            if *b != bucket {
+                return Err(Error::msg(format!(
+                    "store of {path} is for bucket {b}, not {bucket}"
+                )));
+            }
+        }
+
+        if let Some(existing) = self.registered.get(&key) {
+            if *existing != config {
+                return Err(Error::msg(format!(
+                    "{key} is used with different store settings"
+                )));
+            }
+            return Ok(());
+        }
+
+        info!("register object store {key}");
+        let store = build_store(&config, &url, &bucket)?;
+        ctx.runtime_env()
+            .register_object_store(&Url::parse(&key)?, store);
+        self.registered.insert(key, config);
+        Ok(())
+    }
+}
+
+fn build_store(
+    config: &StoreConfig,
+    url: &Url,
+    bucket: &str,
+) -> eyre::Result<Arc<dyn ObjectStore>> {
+    match config.kind {
+        StoreKind::Local => {
+            let root = config
+                .root
+                .as_ref()
+                .ok_or_else(|| Error::msg(format!("local store of {url} without root")))?;
+            std::fs::create_dir_all(root)?;
+            Ok(Arc::new(LocalFileSystem::new_with_prefix(root)?))
+        }
+        StoreKind::S3 => {
+            if !matches!(url.scheme(), "s3" | "s3a") {
+                return Err(Error::msg(format!(
+                    "s3 store for {url}, expect a s3:// path"
+                )));
+            }
+
+            let mut builder = AmazonS3Builder::from_env().with_bucket_name(bucket);
+            let keys = resolve_credentials(config)?;
+            if let Some(v) = config.region.as_ref().or(keys.region.as_ref()) {
+                builder = builder.with_region(v);
+            }
+            if let Some(v) = &config.endpoint {
+                builder = builder
+                    .with_endpoint(v)
+                    .with_allow_http(config.allow_http.unwrap_or(v.starts_with("http://")));
+            } else if let Some(v) = config.allow_http {
+                builder = builder.with_allow_http(v);
+            }
+            if let Some(v) = keys.access_id {
+                builder = builder.with_access_key_id(v);
+            }
+            if let Some(v) = keys.secret_key {
+                builder = builder.with_secret_access_key(v);
+            }
+            if let Some(v) = keys.session_token {
+                builder = builder.with_token(v);
+            }
+            Ok(Arc::new(builder.build()?))
+        }
+    }
+}
+
+/// credentials of a store, unset ones are left to the environment
+#[derive(Debug, Default)]
+struct Keys {
+    access_id: Option<String>,
+    secret_key: Option<String>,
+    session_token: Option<String>,
+    region: Option<String>,
+}
+
+fn resolve_credentials(config: &StoreConfig) -> eyre::Result<Keys> {
+    let inline = config.access_id.is_some() || config.secret_key.is_some();
+    match &config.credentials {
+        None => Ok(Keys {
+            access_id: config.access_id.clone(),
+            secret_key: config.secret_key.clone(),
+            ..Default::default()
+        }),
+        Some(_) if inline => Err(Error::msg(
+            "store has both inline keys and credentials, use one of them",
+        )),
+        Some(Credentials::Env {
+            access_id,
+            secret_key,
+            session_token,
+        }) => {
+            let var = |name: &Option<String>, default: &str| {
+                let name = name.as_deref().unwrap_or(default);
+                std::env::var(name)
+                    .map_err(|_| Error::msg(format!("environment variable {name} is not set")))
+            };
+            Ok(Keys {
+                access_id: Some(var(access_id, "AWS_ACCESS_KEY_ID")?),
+                secret_key: Some(var(secret_key, "AWS_SECRET_ACCESS_KEY")?),
+                session_token: match session_token {
+                    Some(_) => Some(var(session_token, "AWS_SESSION_TOKEN")?),
+                    None => std::env::var("AWS_SESSION_TOKEN").ok(),
+                },
+                region: None,
+            })
+        }
+        Some(Credentials::Profile { name, file }) => {
+            let name = name
+                .clone()
+                .or_else(|| std::env::var("AWS_PROFILE").ok())
+                .unwrap_or_else(|| "default".to_owned());
+            let file = match file {
+                Some(f) => f.clone(),
+                None => match std::env::var("AWS_SHARED_CREDENTIALS_FILE") {
+                    Ok(f) => f,
+                    Err(_) => format!(
+                        "{}/.aws/credentials",
+                        std::env::var("HOME").unwrap_or_default()
+                    ),
+                },
+            };
+            let contents = std::fs::read_to_string(&file)
+                .map_err(|e| Error::msg(format!("read credentials file {file}: {e}")))?;
+            let mut profile = parse_profile(&contents, &name)
+                .ok_or_else(|| Error::msg(format!("profile {name} not found in {file}")))?;
+            let mut key = |k: &str| profile.remove(k);
+            let keys = Keys {
+                access_id: key("aws_access_key_id"),
+                secret_key: key("aws_secret_access_key"),
+                session_token: key("aws_session_token"),
+                region: key("region"),
+            };
+            if keys.access_id.is_none() || keys.secret_key.is_none() {
+                return Err(Error::msg(format!(
+                    "profile {name} in {file} lacks aws_access_key_id or aws_secret_access_key"
+                )));
+            }
+            Ok(keys)
+        }
+    }
+}
+
+/// keys of section `[name]` or `[profile name]` of an AWS style ini file
+fn parse_profile(contents: &str, name: &str) -> Option<HashMap<String, String>> {
+    let mut result: Option<HashMap<String, String>> = None;
+    let mut in_section = false;
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            let section = section.trim();
+            in_section =
+                section == name || section.strip_prefix("profile ").map(str::trim) == Some(name);
+            if in_section {
+                result.get_or_insert_with(HashMap::new);
+            }
+            continue;
+        }
+        if let (true, Some((k, v))) = (in_section, line.split_once('=')) {
+            if let Some(profile) = result.as_mut() {
+                profile.insert(k.trim().to_lowercase(), v.trim().to_owned());
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CREDENTIALS: &str = "
+[default]
+aws_access_key_id = AKIDDEFAULT
+aws_secret_access_key = default-secret
+
+# staging keys
+[profile staging]
+aws_access_key_id=AKIDSTAGING
+aws_secret_access_key=staging-secret
+region = eu-west-1
+";
+
+    #[test]
+    fn test_parse_profile() {
+        let default = parse_profile(CREDENTIALS, "default").unwrap();
+        assert_eq!(default["aws_access_key_id"], "AKIDDEFAULT");
+        assert_eq!(default.get("region"), None);
+
+        let staging = parse_profile(CREDENTIALS, "staging").unwrap();
+        assert_eq!(staging["aws_secret_access_key"], "staging-secret");
+        assert_eq!(staging["region"], "eu-west-1");
+    }
+
+    #[test]
+    fn test_parse_profile_missing() {
+        assert!(parse_profile(CREDENTIALS, "prod").is_none());
+    }
+
+    #[test]
+    fn test_store_unknown_field() {
+        let config = "{bucket: data, region: eu-west-1, credentials: {profile: {name: ci}}}";
+        assert!(serde_yaml::from_str::<StoreRef>(config).is_ok());
+        assert!(serde_yaml::from_str::<StoreRef>("{bucket: data, regoin: eu-west-1}").is_err());
+        assert!(serde_yaml::from_str::<StoreConfig>("credentials: {env: {acces_id: ID}}").is_err());
+        let config = serde_yaml::from_str::<StoreConfig>("credentials: {env: {}}").unwrap();
+        assert!(matches!(config.credentials, Some(Credentials::Env { .. })));
+        assert_eq!(
+            serde_yaml::from_str::<StoreConfig>("bucket: data")
+                .unwrap()
+                .credentials,
+            None
+        );
+        assert_eq!(
+            serde_yaml::from_str::<StoreRef>("warehouse").unwrap(),
+            StoreRef::Named("warehouse".to_owned())
+        );
+    }
+}
@@ -1,6 +1,12 @@
 use std::collections::HashSet;
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+use arrow::compute::concat_batches;
+use arrow_array::RecordBatch;
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use parquet::arrow::arrow_reader::{ArrowReaderMetadata, ParquetRecordBatchReaderBuilder};
 
 pub fn open_file<P: AsRef<Path>>(file_name: P) -> std::io::Result<File> {
     let file_name = file_name.as_ref();
@@ -11,3 +17,159 @@ pub fn open_file<P: AsRef<Path>>(file_name: P) -> std::io::Result<File> {
 pub fn hashset(data: Vec<i32>) -> HashSet<i32> {
     HashSet::from_iter(data.iter().cloned())
 }
+
+/// a new directory below `parent`, removed with its content when dropped
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new<P: AsRef<Path>>(parent: P, prefix: &str) -> std::io::Result<Self> {
+        let path = parent
+            .as_ref()
+            .join(format!("{prefix}-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&path)?;
+        Ok(TempDir { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.path) {
+            warn!("can not remove {:?}: {e}", self.path);
+        }
+    }
+}
+
+/// decode row group `idx` of `file` into a single record batch
+pub fn read_row_group(
+    file: &File,
+    metadata: &ArrowReaderMetadata,
+    idx: usize,
+) -> eyre::Result<RecordBatch> {
+    let rows = metadata.metadata().row_group(idx).num_rows() as usize;
+    let reader =
+        ParquetRecordBatchReaderBuilder::new_with_metadata(file.try_clone()?, metadata.clone())
+            .with_row_groups(vec![idx])
+            .with_batch_size(rows.max(1))
+            .build()?;
+
+    let batches = reader.collect::<Result<Vec<_>, _>>()?;
+    Ok(concat_batches(metadata.schema(), &batches)?)
+}
+
+/// parse a size like `268435456`, `512KB`, `256MB` or `1GB`, units are powers of 1024
+pub fn parse_size(value: &str) -> Result<usize, String> {
+    let value = value.trim().to_uppercase();
+    let digits = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = match &value[digits.len()..] {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        v => return Err(format!("unknown size unit {v}")),
+    };
+    match digits.trim().parse::<usize>() {
+        Ok(0) => Err(format!("size {value} must be positive")),
+        Ok(v) => v
+            .checked_mul(unit)
+            .ok_or_else(|| format!("size {value} is too large")),
+        Err(e) => Err(format!("invalid size {value}: {e}")),
+    }
+}
+
+/// parse a duration like `30s`, `15m`, `1h` or `1d` into seconds
+pub fn parse_duration(value: &str) -> Result<i64, String> {
+    let value = value.trim().to_lowercase();
+    let digits = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = match &value[digits.len()..] {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        v => return Err(format!("unknown duration unit {v:?}, expect s, m, h or d")),
+    };
+    match digits.trim().parse::<i64>() {
+        Ok(v) if v > 0 => v
+            .checked_mul(unit)
+            .ok_or_else(|| format!("duration {value} is too large")),
+        Ok(_) => Err(format!("duration {value} must be positive")),
+        Err(e) => Err(format!("invalid duration {value}: {e}")),
+    }
+}
+
+/// 32 bit MurmurHash3 (x86 variant) of `data`
+pub fn murmur3_32(data: &[u8], seed: u32) -> u32 {
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+
+    let mut h = seed;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        h ^= k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        h = h.rotate_left(13).wrapping_mul(5).wrapping_add(0xe6546b64);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let k = tail
+            .iter()
+            .enumerate()
+            .fold(0u32, |k, (i, &b)| k | ((b as u32) << (8 * i)));
+        h ^= k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    }
+
+    h ^= data.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85ebca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2ae35);
+    h ^= h >> 16;
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("256MB"), Ok(256 * 1024 * 1024));
+        assert_eq!(parse_size("2g"), Ok(2 * 1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn test_parse_size_bad_value() {
+        assert!(parse_size("12PB").is_err());
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("17179869184GB").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("1h"), Ok(3600));
+        assert_eq!(parse_duration("1d"), Ok(86400));
+        assert_eq!(parse_duration("15m"), Ok(900));
+        assert!(parse_duration("1w").is_err());
+        assert!(parse_duration("0h").is_err());
+        assert!(parse_duration("9223372036854775807d").is_err());
+    }
+
+    #[test]
+    fn test_murmur3_32() {
+        assert_eq!(murmur3_32(b"", 0), 0);
+        assert_eq!(murmur3_32(b"", 1), 0x514e28b7);
+        assert_eq!(murmur3_32(b"hello", 0), 0x248bfa47);
+        assert_eq!(
+            murmur3_32(b"The quick brown fox jumps over the lazy dog", 0),
+            0x2e4ff723
+        );
+    }
+}
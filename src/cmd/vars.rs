@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use eyre::Error;
+use serde_yaml::Value;
+
+/// values of `${VAR}` in configs, `--set` ones override the environment
+pub struct Vars {
+    set: HashMap<String, String>,
+}
+
+impl Vars {
+    pub fn new(set: &[(String, String)]) -> Self {
+        Vars {
+            set: set.iter().cloned().collect(),
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        self.set
+            .get(name)
+            .cloned()
+            .or_else(|| std::env::var(name).ok())
+    }
+
+    /// expand `${VAR}` and `${VAR:-default}` in `value`, `$${` is a literal `${`.
+    /// Returns the names of unresolved variables
+    pub fn expand(&self, value: &str) -> Result<(String, Vec<String>), String> {
+        let mut result = String::with_capacity(value.len());
+        let mut missing = vec![];
+        let mut rest = value;
+        while let Some(start) = rest.find('$') {
+            result.push_str(&rest[..start]);
+            let tail = &rest[start..];
+            if tail.starts_with("$${") {
+                result.push_str("${");
+                rest = &tail[3..];
+                continue;
+            }
+            if !tail.starts_with("${") {
+                result.push('$');
+                rest = &tail[1..];
+                continue;
+            }
+
+            let end = tail
+                .find('}')
+                .ok_or_else(|| format!("unclosed ${{ in {value:?}"))?;
+            let spec = &tail[2..end];
+            let (name, default) = match spec.split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (spec, None),
+            };
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+            {
+                return Err(format!("invalid variable name {name:?} in {value:?}"));
+            }
+
+            match (self.get(name), default) {
+                (Some(v), _) => result.push_str(&v),
+                (None, Some(d)) => result.push_str(d),
+                (None, None) => missing.push(name.to_owned()),
+            }
+            rest = &tail[end + 1..];
+        }
+        result.push_str(rest);
+        Ok((result, missing))
+    }
+
+    /// expand the variables of every string in `value`, `source` is the config
+    /// text the value was parsed from, used for line numbers in errors
+    pub fn substitute(&self, value: &mut Value, source: &str) -> eyre::Result<()> {
+        let mut errors = vec![];
+        self.walk(value, "", &mut errors);
+        if errors.is_empty() {
+            return Ok(());
+        }
+
+        let messages = errors
+            .into_iter()
+            .map(|(path, error)| {
+                let line = error
+                    .strip_prefix("unresolved variable ")
+                    .and_then(|name| {
+                        source
+                            .lines()
+                            .position(|l| l.contains(&format!("${{{name}}}")))
+                    })
+                    .map(|l| format!(" (line {})", l + 1))
+                    .unwrap_or_default();
+                format!("{error} at {path}{line}")
+            })
+            .collect::<Vec<_>>();
+        Err(Error::msg(messages.join("\n")))
+    }
+
+    fn walk(&self, value: &mut Value, path: &str, errors: &mut Vec<(String, String)>) {
+        match value {
+            Value::String(s) => match self.expand(s) {
+                Ok((expanded, missing)) if missing.is_empty() => {
+                    if expanded != *s {
+                        *value = typed(s, expanded);
+                    }
+                }
+                Ok((_, missing)) => {
+                    for name in missing {
+                        errors.push((path.to_owned(), format!("unresolved variable {name}")));
+                    }
+                }
+                Err(e) => errors.push((path.to_owned(), e)),
+            },
+            Value::Sequence(items) => {
+                for (i, item) in items.iter_mut().enumerate() {
+                    self.walk(item, &format!("{path}[{i}]"), errors);
+                }
+            }
+            Value::Mapping(map) => {
+                for (key, item) in map.iter_mut() {
+                    let key = match key {
+                        Value::String(k) => k.clone(),
+                        k => serde_yaml::to_string(k)
+                            .unwrap_or_default()
+                            .trim()
+                            .to_owned(),
+                    };
+                    let path = match path {
+                        "" => key,
+                        p => format!("{p}.{key}"),
+                    };
+                    self.walk(item, &path, errors);
+                }
+            }
+            Value::Tagged(tagged) => self.walk(&mut tagged.value, path, errors),
+            _ => {}
+        }
+    }
+}
+
+/// a string made of a single variable takes the scalar type of its value,
+/// so `max_group_size: ${ROWS}` stays a number. Values that would not read
+/// back the same, like `1.10` or `1e3`, stay strings
+fn typed(original: &str, expanded: String) -> Value {
+    let single = original.starts_with("${") && original.find('}') == Some(original.len() - 1);
+    if single {
+        if let Ok(v @ (Value::Bool(_) | Value::Number(_))) = serde_yaml::from_str(&expanded) {
+            let same = serde_yaml::to_string(&v).map_or(false, |s| s.trim() == expanded);
+            if same {
+                return v;
+            }
+        }
+    }
+    Value::String(expanded)
+}
+
+/// parse `key=value` of `--set`
+pub fn parse_var(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(k, v)| (k.trim().to_owned(), v.to_owned()))
+        .ok_or_else(|| format!("expect key=value, got {value}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> Vars {
+        Vars::new(&[
+            ("DATE".to_owned(), "2024-03-01".to_owned()),
+            ("ROWS".to_owned(), "86400".to_owned()),
+        ])
+    }
+
+    #[test]
+    fn test_expand() {
+        let (v, missing) = vars().expand("data/${DATE}/${DEVICE:-d1}.csv").unwrap();
+        assert_eq!(v, "data/2024-03-01/d1.csv");
+        assert!(missing.is_empty());
+
+        let (v, _) = vars().expand("cost $5, $${DATE}").unwrap();
+        assert_eq!(v, "cost $5, ${DATE}");
+    }
+
+    #[test]
+    fn test_expand_unresolved() {
+        let (_, missing) = vars().expand("${PP_TEST_UNSET_VAR}").unwrap();
+        assert_eq!(missing, vec!["PP_TEST_UNSET_VAR"]);
+        assert!(vars().expand("${DATE").is_err());
+    }
+
+    #[test]
+    fn test_substitute() {
+        let source = "sink:\n  path: ${DATE}.parquet\n  rows: ${ROWS}\nquery:\n  q: select ${PP_TEST_UNSET_VAR}\n";
+        let mut value: Value = serde_yaml::from_str(source).unwrap();
+        let err = vars().substitute(&mut value, source).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unresolved variable PP_TEST_UNSET_VAR at query.q (line 5)"
+        );
+
+        let source = "sink:\n  path: ${DATE}.parquet\n  rows: ${ROWS}\n";
+        let mut value: Value = serde_yaml::from_str(source).unwrap();
+        vars().substitute(&mut value, source).unwrap();
+        assert_eq!(value["sink"]["path"], Value::from("2024-03-01.parquet"));
+        assert_eq!(value["sink"]["rows"], Value::from(86400));
+    }
+
+    #[test]
+    fn test_typed() {
+        assert_eq!(typed("${V}", "86400".to_owned()), Value::from(86400));
+        assert_eq!(typed("${V}", "-0.5".to_owned()), Value::from(-0.5));
+        assert_eq!(typed("${V}", "true".to_owned()), Value::from(true));
+        assert_eq!(typed("${V}", "1.10".to_owned()), Value::from("1.10"));
+        assert_eq!(typed("${V}", "1e3".to_owned()), Value::from("1e3"));
+        assert_eq!(typed("v${V}", "v1".to_owned()), Value::from("v1"));
+    }
+
+    #[test]
+    fn test_unresolved_line() {
+        let source = "a: ${PP_TEST_UNSET_VAR_X:-x}\nb: ${PP_TEST_UNSET_VAR}\n";
+        let mut value: Value = serde_yaml::from_str(source).unwrap();
+        let err = vars().substitute(&mut value, source).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unresolved variable PP_TEST_UNSET_VAR at b (line 2)"
+        );
+    }
+
+    #[test]
+    fn test_parse_var() {
+        assert_eq!(
+            parse_var("DATE=2024-03-01"),
+            Ok(("DATE".to_owned(), "2024-03-01".to_owned()))
+        );
+        assert!(parse_var("DATE").is_err());
+    }
+}
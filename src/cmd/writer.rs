@@ -0,0 +1,355 @@
+use std::fmt::Display;
+use std::io::Write;
+use std::str::FromStr;
+
+use arrow_array::RecordBatch;
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use parquet::arrow::arrow_writer::{
+    compute_leaves, get_column_writers, ArrowColumnWriter, ArrowLeafColumn,
+};
+use parquet::basic::{Compression, Encoding};
+use parquet::column::writer::ColumnCloseResult;
+use parquet::file::metadata::{ParquetMetaData, RowGroupMetaData};
+use parquet::file::properties::{
+    EnabledStatistics, WriterProperties, WriterPropertiesBuilder, WriterPropertiesPtr,
+};
+use parquet::file::reader::ChunkReader;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::format::SortingColumn;
+
+#[derive(clap::Args, Debug, Clone)]
+/// options for the parquet files written by merge and split
+pub struct WriterArgs {
+    #[arg(long, default_value_t = false)]
+    /// keep created_by and key-value metadata (including ARROW:schema) of the first input
+    keep_metadata: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// keep sorting columns of the input row groups, only when they are copied
+    keep_sorting: bool,
+
+    #[arg(long)]
+    /// created_by string to write, overrides the one kept by --keep-metadata
+    created_by: Option<String>,
+
+    #[arg(long, default_value_t = false)]
+    /// decode and re-encode row groups instead of copying column chunks
+    pub reencode: bool,
+
+    #[arg(long, value_parser = parse_compression::<Compression>)]
+    /// compression used when re-encoding, e.g. snappy, zstd or ZSTD(3)
+    compression: Option<Compression>,
+
+    #[arg(long, value_parser = parse_encoding::<Encoding>)]
+    /// encoding used when re-encoding, e.g. PLAIN or DELTA_BINARY_PACKED
+    encoding: Option<Encoding>,
+
+    #[arg(long)]
+    /// use dictionary encoding when re-encoding, default is true
+    dictionary: Option<bool>,
+
+    #[arg(long, value_parser = parse_statistics)]
+    /// write statistics when re-encoding, true, false, none, chunk or page
+    statistic: Option<EnabledStatistics>,
+
+    #[arg(short, long, default_value_t = 1)]
+    /// number of threads encoding outputs or the columns of a row group
+    pub jobs: usize,
+}
+
+impl WriterArgs {
+    /// build writer properties for an output derived from `inputs`,
+    /// metadata is taken from the first one
+    pub fn properties(&self, inputs: &[&ParquetMetaData]) -> WriterProperties {
+        self.builder(inputs).build()
+    }
+
+    /// like [`WriterArgs::properties`], for callers adding more settings
+    pub fn builder(&self, inputs: &[&ParquetMetaData]) -> WriterPropertiesBuilder {
+        let mut props = WriterProperties::builder();
+
+        if let Some(first) = inputs.first() {
+            let file_meta = first.file_metadata();
+            if self.keep_metadata {
+                if let Some(created_by) = file_meta.created_by() {
+                    props = props.set_created_by(created_by.to_owned());
+                }
+                props = props.set_key_value_metadata(file_meta.key_value_metadata().cloned());
+            }
+            // re-encoded row groups may be rebuilt in another order
+            if self.keep_sorting && self.reencode {
+                warn!("--keep-sorting only applies when row groups are copied, ignored");
+            } else if self.keep_sorting {
+                props = props.set_sorting_columns(common_sorting_columns(inputs));
+            }
+        }
+
+        if let Some(created_by) = &self.created_by {
+            props = props.set_created_by(created_by.clone());
+        }
+
+        if !self.reencode {
+            if self.compression.is_some()
+                || self.encoding.is_some()
+                || self.dictionary.is_some()
+                || self.statistic.is_some()
+            {
+                warn!("compression, encoding, dictionary and statistic only apply with --reencode, ignored");
+            }
+            return props;
+        }
+
+        if let Some(v) = self.compression {
+            props = props.set_compression(v);
+        }
+        if let Some(v) = self.encoding {
+            props = props.set_encoding(v);
+        }
+        if let Some(v) = self.dictionary {
+            props = props.set_dictionary_enabled(v);
+        }
+        if let Some(v) = self.statistic {
+            props = props.set_statistics_enabled(v);
+        }
+
+        props
+    }
+}
+
+/// sorting columns shared by every row group of the inputs, None if they differ
+fn common_sorting_columns(inputs: &[&ParquetMetaData]) -> Option<Vec<SortingColumn>> {
+    let mut groups = inputs.iter().flat_map(|m| m.row_groups());
+    let expected = groups.next()?.sorting_columns().cloned();
+    for rg in groups {
+        if rg.sorting_columns() != expected.as_ref() {
+            warn!("inputs have different sorting columns, not kept");
+            return None;
+        }
+    }
+    expected
+}
+
+// the parsers below are shared with the sink parameters of `cmd::df`, which
+// uses the parquet version of datafusion, hence generic over the parsed type
+
+/// parse an encoding like PLAIN or DELTA_BINARY_PACKED, case insensitive.
+/// Dictionary encodings are not column encodings, parquet panics on them
+pub fn parse_encoding<T>(value: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let encoding = value.trim().to_uppercase();
+    if encoding.contains("DICTIONARY") {
+        return Err(format!(
+            "{encoding} is not a column encoding, enable dictionaries with --dictionary or `dictionary: true`"
+        ));
+    }
+    T::from_str(&encoding).map_err(|e| format!("unknown encoding {value}: {e}"))
+}
+
+/// parse a compression like snappy, zstd or ZSTD(3), case insensitive. Parquet
+/// wants a level for gzip, brotli and zstd, without one they get its default
+pub fn parse_compression<T>(value: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let compression = match value.trim().to_uppercase().as_str() {
+        "GZIP" => "GZIP(6)".to_owned(),
+        "BROTLI" => "BROTLI(1)".to_owned(),
+        "ZSTD" => "ZSTD(1)".to_owned(),
+        v => v.to_owned(),
+    };
+    T::from_str(&compression).map_err(|e| format!("unknown compression {value}: {e}"))
+}
+
+pub fn parse_statistics(value: &str) -> Result<EnabledStatistics, String> {
+    match value.trim().to_lowercase().as_str() {
+        "false" | "none" => Ok(EnabledStatistics::None),
+        "true" | "chunk" => Ok(EnabledStatistics::Chunk),
+        "page" => Ok(EnabledStatistics::Page),
+        v => Err(format!(
+            "unknown statistic {v}, expect true, false, none, chunk or page"
+        )),
+    }
+}
+
+/// append the column chunks of `rg` from `reader` to `writer` without decoding
+pub fn copy_row_group<W: Write + Send, R: ChunkReader>(
+    writer: &mut SerializedFileWriter<W>,
+    reader: &R,
+    rg: &RowGroupMetaData,
+) -> eyre::Result<()> {
+    let mut rg_out = writer.next_row_group()?;
+    for column in rg.columns() {
+        let result = ColumnCloseResult {
+            bytes_written: column.compressed_size() as _,
+            rows_written: rg.num_rows() as _,
+            metadata: column.clone(),
+            bloom_filter: None,
+            column_index: None,
+            offset_index: None,
+        };
+        rg_out.append_column(reader, result)?;
+    }
+    rg_out.close()?;
+    Ok(())
+}
+
+/// encode `batch` as one row group of `writer`, the columns are split
+/// between up to `jobs` threads
+pub fn encode_row_group<W: Write + Send>(
+    writer: &mut SerializedFileWriter<W>,
+    props: &WriterPropertiesPtr,
+    batch: &RecordBatch,
+    jobs: usize,
+) -> eyre::Result<()> {
+    let schema = batch.schema();
+    let col_writers = get_column_writers(writer.schema_descr(), props, &schema)?;
+
+    let mut leaves = vec![];
+    for (field, column) in schema.fields().iter().zip(batch.columns()) {
+        leaves.extend(compute_leaves(field, column)?);
+    }
+    if leaves.len() != col_writers.len() {
+        return Err(eyre::eyre!("arrow schema does not match parquet schema"));
+    }
+
+    // contiguous runs of columns per thread keep the results in column order
+    let mut columns = col_writers.into_iter().zip(leaves);
+    let per_job = columns.len().div_ceil(jobs.max(1)).max(1);
+    let mut runs = vec![];
+    loop {
+        let run = columns.by_ref().take(per_job).collect::<Vec<_>>();
+        if run.is_empty() {
+            break;
+        }
+        runs.push(run);
+    }
+
+    let encode = |run: Vec<(ArrowColumnWriter, ArrowLeafColumn)>| {
+        run.into_iter()
+            .map(|(mut col_writer, leaf)| {
+                col_writer.write(&leaf)?;
+                Ok(col_writer.close()?)
+            })
+            .collect::<eyre::Result<Vec<_>>>()
+    };
+    let closed = match runs.len() {
+        0 | 1 => runs.into_iter().map(encode).collect::<Vec<_>>(),
+        _ => std::thread::scope(|s| {
+            let handles = runs
+                .into_iter()
+                .map(|run| s.spawn(move || encode(run)))
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| {
+                    h.join()
+                        .unwrap_or_else(|_| Err(eyre::eyre!("column encoder panicked")))
+                })
+                .collect()
+        }),
+    };
+
+    let mut rg_out = writer.next_row_group()?;
+    for result in closed {
+        for chunk in result? {
+            chunk.append_to_row_group(&mut rg_out)?;
+        }
+    }
+    rg_out.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{Float64Array, Int64Array, StringArray};
+    use arrow_array::ArrayRef;
+    use parquet::arrow::arrow_to_parquet_schema;
+    use parquet::basic::{BrotliLevel, GzipLevel, ZstdLevel};
+
+    use super::*;
+
+    #[test]
+    fn test_parse_compression() {
+        assert_eq!(
+            parse_compression::<Compression>("zstd"),
+            Ok(Compression::ZSTD(ZstdLevel::default()))
+        );
+        assert_eq!(
+            parse_compression::<Compression>("ZSTD(3)"),
+            Ok(Compression::ZSTD(ZstdLevel::try_new(3).unwrap()))
+        );
+        assert_eq!(
+            parse_compression::<Compression>("gzip"),
+            Ok(Compression::GZIP(GzipLevel::default()))
+        );
+        assert_eq!(
+            parse_compression::<Compression>("brotli"),
+            Ok(Compression::BROTLI(BrotliLevel::default()))
+        );
+        assert!(parse_compression::<Compression>("bad").is_err());
+    }
+
+    #[test]
+    fn test_parse_encoding() {
+        assert_eq!(parse_encoding::<Encoding>("plain"), Ok(Encoding::PLAIN));
+        assert_eq!(
+            parse_encoding::<Encoding>("DELTA_BINARY_PACKED"),
+            Ok(Encoding::DELTA_BINARY_PACKED)
+        );
+        assert!(parse_encoding::<Encoding>("bad").is_err());
+        assert!(parse_encoding::<Encoding>("RLE_DICTIONARY").is_err());
+        assert!(parse_encoding::<Encoding>("plain_dictionary").is_err());
+    }
+
+    #[test]
+    fn test_parse_statistics() {
+        assert_eq!(parse_statistics("false"), Ok(EnabledStatistics::None));
+        assert_eq!(parse_statistics("page"), Ok(EnabledStatistics::Page));
+        assert!(parse_statistics("yes").is_err());
+    }
+
+    /// bytes of a file with `batch` encoded as one row group by `jobs` threads
+    fn encode(batch: &RecordBatch, jobs: usize) -> Vec<u8> {
+        let schema = arrow_to_parquet_schema(&batch.schema()).unwrap();
+        let props = Arc::new(WriterProperties::builder().build());
+        let mut writer =
+            SerializedFileWriter::new(vec![], schema.root_schema_ptr(), props.clone()).unwrap();
+        encode_row_group(&mut writer, &props, batch, jobs).unwrap();
+        writer.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_encode_row_group_jobs() {
+        let batch = RecordBatch::try_from_iter([
+            (
+                "a",
+                Arc::new(Int64Array::from_iter_values(0..1000)) as ArrayRef,
+            ),
+            (
+                "b",
+                Arc::new(StringArray::from_iter_values(
+                    (0..1000).map(|i| format!("v{}", i % 7)),
+                )),
+            ),
+            (
+                "c",
+                Arc::new(Float64Array::from_iter_values(
+                    (0..1000).map(|i| i as f64 / 3.0),
+                )),
+            ),
+        ])
+        .unwrap();
+
+        let expected = encode(&batch, 1);
+        for jobs in [2, 3, 8] {
+            assert_eq!(encode(&batch, jobs), expected, "jobs {jobs}");
+        }
+    }
+}
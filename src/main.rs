@@ -11,6 +11,7 @@ enum Commands {
     Merge(cmd::merge::Args),
     Split(cmd::split::Args),
     Df(cmd::df::Args),
+    Apply(cmd::apply::Args),
 }
 
 #[derive(Parser, Debug)]
@@ -37,5 +38,6 @@ fn main() -> Result<()> {
         Commands::Merge(args) => cmd::merge::merge_main(args),
         Commands::Split(args) => cmd::split::split_main(args),
         Commands::Df(args) => cmd::df::df_main(args),
+        Commands::Apply(args) => cmd::apply::apply_main(args),
     }
 }